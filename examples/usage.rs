@@ -1,7 +1,8 @@
 extern crate serde_resp;
 
-use serde_resp::to_string;
+use serde_resp::{from_str, to_string};
 
 fn main() {
     println!("{:?}", to_string(&"foobar").unwrap());
-}
\ No newline at end of file
+    println!("{:?}", from_str::<String>("$6\r\nfoobar\r\n").unwrap());
+}
@@ -0,0 +1,2265 @@
+use serde::de::{self, Deserialize, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+                Unexpected, VariantAccess, Visitor};
+use error::{Error, ErrorKind, RedisError, Result};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::marker::PhantomData;
+use std::str::{self, FromStr};
+
+// A single RESP token. Aggregates only carry their header, the elements follow in the input.
+// Their length is None for RESP3 streamed aggregates, which end with ".\r\n".
+enum Token<'de> {
+    SimpleString(&'de [u8]),
+    Error(&'de [u8]),
+    Integer(i64),
+    BulkString(&'de [u8]),
+    Null,
+    Boolean(bool),
+    Double(&'de [u8]),
+    BigNumber(&'de [u8]),
+    Array(Option<usize>),
+    Map(Option<usize>),
+}
+
+// Limits protecting the deserializer against malicious or broken peers. Exceeding any of them
+// fails with the matching ErrorKind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeserializerOptions {
+    max_depth: usize,
+    max_bulk_len: usize,
+    max_aggregate_len: usize,
+    max_inline_len: usize,
+    max_total_bytes: usize,
+}
+
+impl DeserializerOptions {
+    // Defaults are a nesting depth of 128, bulk strings of 512 MB as Redis' proto-max-bulk-len,
+    // aggregates of 16M elements, far beyond practical replies, lines of 64 KB as Redis'
+    // proto-inline-max-size, and no limit on the size of a frame. A frame is only bounded by the
+    // other limits then, so set max_total_bytes when reading from an untrusted peer.
+    pub fn new() -> Self {
+        DeserializerOptions {
+            max_depth: 128,
+            max_bulk_len: 512 * 1024 * 1024,
+            max_aggregate_len: 16 * 1024 * 1024,
+            max_inline_len: 64 * 1024,
+            max_total_bytes: usize::MAX,
+        }
+    }
+
+    // Maximum number of nested arrays.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // Maximum length in bytes of a bulk string.
+    pub fn max_bulk_len(mut self, max_bulk_len: usize) -> Self {
+        self.max_bulk_len = max_bulk_len;
+        self
+    }
+
+    // Maximum number of elements in an array or entries in a map, streamed ones included.
+    pub fn max_aggregate_len(mut self, max_aggregate_len: usize) -> Self {
+        self.max_aggregate_len = max_aggregate_len;
+        self
+    }
+
+    // Maximum length in bytes of a line, such as a simple string, an error or a length, without
+    // its type marker and "\r\n".
+    pub fn max_inline_len(mut self, max_inline_len: usize) -> Self {
+        self.max_inline_len = max_inline_len;
+        self
+    }
+
+    // Maximum size in bytes of a whole frame.
+    pub fn max_total_bytes(mut self, max_total_bytes: usize) -> Self {
+        self.max_total_bytes = max_total_bytes;
+        self
+    }
+
+    fn check_bulk_len(&self, len: usize) -> Result<()> {
+        if len > self.max_bulk_len {
+            return Err(ErrorKind::BulkLengthLimitExceeded(self.max_bulk_len).into());
+        }
+        Ok(())
+    }
+
+    fn check_aggregate_len(&self, len: usize) -> Result<()> {
+        if len > self.max_aggregate_len {
+            return Err(ErrorKind::AggregateLengthLimitExceeded(self.max_aggregate_len).into());
+        }
+        Ok(())
+    }
+
+    fn check_inline_len(&self, len: usize) -> Result<()> {
+        if len > self.max_inline_len {
+            return Err(ErrorKind::InlineLengthLimitExceeded(self.max_inline_len).into());
+        }
+        Ok(())
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<()> {
+        if depth > self.max_depth {
+            return Err(ErrorKind::DepthLimitExceeded(self.max_depth).into());
+        }
+        Ok(())
+    }
+
+    fn check_total_bytes(&self, total: usize) -> Result<()> {
+        if total > self.max_total_bytes {
+            return Err(ErrorKind::TotalBytesLimitExceeded(self.max_total_bytes).into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        DeserializerOptions::new()
+    }
+}
+
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+    pos: usize,
+    depth: usize,
+    options: DeserializerOptions,
+}
+
+impl<'de> Deserializer<'de> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &'de str) -> Self {
+        Deserializer::from_slice(input.as_bytes())
+    }
+
+    pub fn from_slice(input: &'de [u8]) -> Self {
+        Deserializer { input, pos: 0, depth: 0, options: DeserializerOptions::new() }
+    }
+
+    pub fn with_options(mut self, options: DeserializerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Make sure the whole input has been consumed.
+    pub fn end(&mut self) -> Result<()> {
+        if self.pos == self.input.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::DeError("trailing characters".to_owned()).into())
+        }
+    }
+
+    fn peek_byte(&self) -> Result<u8> {
+        self.input.get(self.pos).cloned().ok_or_else(|| incomplete(None))
+    }
+
+    // Read everything up to the next "\r\n" and consume the terminator.
+    fn read_line(&mut self) -> Result<&'de [u8]> {
+        let input = self.input;
+        let rest = &input[self.pos..];
+        let end = rest.iter().position(|&b| b == b'\r');
+        self.options.check_inline_len(end.unwrap_or(rest.len()))?;
+        match end {
+            Some(i) if i + 1 < rest.len() => {
+                if rest[i + 1] != b'\n' {
+                    return Err(ErrorKind::DeError("expected '\\n' after '\\r'".to_owned()).into());
+                }
+                self.options.check_total_bytes(self.pos + i + 2)?;
+                self.pos += i + 2;
+                Ok(&rest[..i])
+            }
+            Some(i) => {
+                self.options.check_total_bytes(self.pos + i + 2)?;
+                Err(incomplete(Some(1)))
+            }
+            None => {
+                self.options.check_total_bytes(self.pos + rest.len() + 2)?;
+                Err(incomplete(Some(2)))
+            }
+        }
+    }
+
+    // Read exactly `len` bytes followed by "\r\n".
+    fn read_bulk(&mut self, len: usize) -> Result<&'de [u8]> {
+        let input = self.input;
+        let rest = &input[self.pos..];
+        self.options.check_total_bytes(self.pos.saturating_add(len).saturating_add(2))?;
+        if rest.len() < len + 2 {
+            return Err(incomplete(Some(len + 2 - rest.len())));
+        }
+        if &rest[len..len + 2] != b"\r\n" {
+            return Err(ErrorKind::DeError("bulk string is not terminated by '\\r\\n'".to_owned()).into());
+        }
+        self.pos += len + 2;
+        Ok(&rest[..len])
+    }
+
+    // Consume the "?" length of a streamed aggregate, if that is what follows.
+    fn read_streamed(&mut self) -> Result<bool> {
+        if self.input.get(self.pos) != Some(&b'?') {
+            return Ok(false);
+        }
+        self.pos += 1;
+        match self.read_line()? {
+            b"" => Ok(true),
+            _ => Err(ErrorKind::DeError("invalid streamed aggregate length".to_owned()).into()),
+        }
+    }
+
+    // Consume the ".\r\n" ending a streamed aggregate, if that is what follows.
+    fn read_stream_end(&mut self) -> Result<bool> {
+        if self.peek_byte()? != b'.' {
+            return Ok(false);
+        }
+        self.pos += 1;
+        match self.read_line()? {
+            b"" => Ok(true),
+            _ => Err(ErrorKind::DeError("invalid end of streamed aggregate".to_owned()).into()),
+        }
+    }
+
+    // Read the length of an array or a map, `None` when it is streamed.
+    fn read_aggregate_length(&mut self) -> Result<Option<Option<usize>>> {
+        if self.read_streamed()? {
+            return Ok(Some(None));
+        }
+        match self.read_length()? {
+            Some(len) => {
+                self.options.check_aggregate_len(len)?;
+                Ok(Some(Some(len)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Read the length of a bulk string or an array. `None` is the null length "-1".
+    fn read_length(&mut self) -> Result<Option<usize>> {
+        let line = self.read_line()?;
+        match parse_str::<i64>(line)? {
+            -1 => Ok(None),
+            len if len >= 0 => Ok(Some(len as usize)),
+            len => Err(ErrorKind::DeError(format!("invalid length: '{}'", len)).into()),
+        }
+    }
+
+    // Attributes describe the value that follows them, they are skipped. A loop rather than
+    // recursion, as any number of attributes may precede a value.
+    fn skip_attributes(&mut self) -> Result<()> {
+        while self.peek_byte()? == b'|' {
+            self.pos += 1;
+            let len = self.read_length()?.ok_or_else(|| Error::from(ErrorKind::DeError("invalid attribute length".to_owned())))?;
+            self.options.check_aggregate_len(len)?;
+            self.skip_values(Some(len.saturating_mul(2)), 2)?;
+        }
+        Ok(())
+    }
+
+    fn next_token(&mut self) -> Result<Token<'de>> {
+        self.skip_attributes()?;
+        let marker = self.peek_byte()?;
+        self.pos += 1;
+        match marker {
+            b'+' => Ok(Token::SimpleString(self.read_line()?)),
+            b'-' => Ok(Token::Error(self.read_line()?)),
+            b':' => Ok(Token::Integer(parse_str(self.read_line()?)?)),
+            b'$' | b'!' => match self.read_length()? {
+                Some(len) => {
+                    self.options.check_bulk_len(len)?;
+                    let v = self.read_bulk(len)?;
+                    Ok(if marker == b'!' { Token::Error(v) } else { Token::BulkString(v) })
+                }
+                None => Ok(Token::Null),
+            },
+            // A verbatim string is a bulk string prefixed with its 3 byte format, "txt:" or "mkd:".
+            b'=' => match self.read_length()? {
+                Some(len) => {
+                    self.options.check_bulk_len(len)?;
+                    let v = self.read_bulk(len)?;
+                    if v.len() < 4 || v[3] != b':' {
+                        return Err(ErrorKind::DeError("verbatim string without format".to_owned()).into());
+                    }
+                    Ok(Token::BulkString(&v[4..]))
+                }
+                None => Ok(Token::Null),
+            },
+            // Sets and pushes are read as arrays.
+            b'*' | b'~' | b'>' => Ok(self.read_aggregate_length()?.map_or(Token::Null, Token::Array)),
+            b'%' => Ok(self.read_aggregate_length()?.map_or(Token::Null, Token::Map)),
+            b'_' => match self.read_line()? {
+                b"" => Ok(Token::Null),
+                _ => Err(ErrorKind::DeError("invalid null".to_owned()).into()),
+            },
+            b',' => Ok(Token::Double(self.read_line()?)),
+            b'(' => Ok(Token::BigNumber(self.read_line()?)),
+            b'#' => match self.read_line()? {
+                b"t" => Ok(Token::Boolean(true)),
+                b"f" => Ok(Token::Boolean(false)),
+                v => Err(ErrorKind::DeError(format!("invalid boolean: '{}'", String::from_utf8_lossy(v))).into()),
+            },
+            _ => {
+                self.pos -= 1;
+                Err(ErrorKind::DeError(format!("invalid type marker: '{}'", marker as char)).into())
+            }
+        }
+    }
+
+    // Consume the next value, including all elements of an array or a map.
+    fn skip_value(&mut self) -> Result<()> {
+        match self.next_token()? {
+            Token::Array(len) => self.skip_values(len, 1),
+            Token::Map(len) => self.skip_values(len.map(|len| len.saturating_mul(2)), 2),
+            _ => Ok(()),
+        }
+    }
+
+    // Consume the elements of an aggregate, up to the end of the stream when `count` is None. Each
+    // entry of the aggregate is `per_entry` values.
+    fn skip_values(&mut self, count: Option<usize>, per_entry: usize) -> Result<()> {
+        self.enter()?;
+        match count {
+            Some(count) => {
+                for _ in 0..count {
+                    self.skip_value()?;
+                }
+            }
+            None => {
+                let mut values: usize = 0;
+                while !self.read_stream_end()? {
+                    values += 1;
+                    self.options.check_aggregate_len(values.div_ceil(per_entry))?;
+                    self.skip_value()?;
+                }
+            }
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    // Go one array deeper. The caller decrements the depth once the array is done.
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        self.options.check_depth(self.depth)
+    }
+
+    fn visit_array<V>(&mut self, len: Option<usize>, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.enter()?;
+        let mut access = Array { de: self, remaining: len, read: 0 };
+        let value = visitor.visit_seq(&mut access)?;
+        access.de.depth -= 1;
+        match access.remaining {
+            Some(0) => Ok(value),
+            // Tuples stop before looking for the end of the stream.
+            None if access.de.read_stream_end()? => Ok(value),
+            // The visitor stopped before the input did.
+            Some(_) => Err(ErrorKind::DeError("trailing elements in array".to_owned()).into()),
+            None => Err(ErrorKind::DeError("trailing elements in streamed array".to_owned()).into()),
+        }
+    }
+
+    // Visit `len` key/value pairs laid out as a flat array [k1, v1, k2, v2, ...] or as a RESP3 map.
+    fn visit_pairs<V>(&mut self, len: Option<usize>, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.enter()?;
+        let mut access = Pairs { de: self, remaining: len, read: 0 };
+        let value = visitor.visit_map(&mut access)?;
+        access.de.depth -= 1;
+        match access.remaining {
+            Some(0) => Ok(value),
+            None if access.de.read_stream_end()? => Ok(value),
+            Some(_) => Err(ErrorKind::DeError("trailing entries in map".to_owned()).into()),
+            None => Err(ErrorKind::DeError("trailing entries in streamed map".to_owned()).into()),
+        }
+    }
+
+    fn deserialize_signed<V>(&mut self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Integer(v) => visitor.visit_i64(v),
+            Token::SimpleString(s) | Token::BulkString(s) | Token::BigNumber(s) => visitor.visit_i64(parse_str(s)?),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_unsigned<V>(&mut self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Integer(v) => visitor.visit_i64(v),
+            Token::SimpleString(s) | Token::BulkString(s) | Token::BigNumber(s) => visitor.visit_u64(parse_str(s)?),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_float<V>(&mut self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Integer(v) => visitor.visit_i64(v),
+            Token::SimpleString(s) | Token::BulkString(s) | Token::Double(s) => visitor.visit_f64(parse_str(s)?),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+}
+
+pub fn from_str<'a, T>(s: &'a str) -> Result<T> where T: Deserialize<'a> {
+    let mut deserializer = Deserializer::from_str(s);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+pub fn from_slice<'a, T>(v: &'a [u8]) -> Result<T> where T: Deserialize<'a> {
+    let mut deserializer = Deserializer::from_slice(v);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+// Deserialise a single RESP frame read from `reader`. Only the bytes of that frame are consumed,
+// so passing `&mut reader` again reads the next frame.
+pub fn from_reader<R, T>(reader: R) -> Result<T> where R: BufRead, T: DeserializeOwned {
+    from_reader_with_options(reader, DeserializerOptions::new())
+}
+
+pub fn from_reader_with_options<R, T>(mut reader: R, options: DeserializerOptions) -> Result<T>
+    where R: BufRead, T: DeserializeOwned
+{
+    let mut buf = Vec::new();
+    read_frame(&mut reader, &mut buf, &options)?;
+    deserialize_frame(&buf, options)
+}
+
+fn deserialize_frame<T>(frame: &[u8], options: DeserializerOptions) -> Result<T> where T: DeserializeOwned {
+    let mut deserializer = Deserializer::from_slice(frame).with_options(options);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+// Input of a StreamDeserializer created from a slice.
+pub struct SliceRead<'de> {
+    slice: &'de [u8],
+}
+
+// Input of a StreamDeserializer created from an io::Read.
+pub struct IoRead<R> {
+    reader: BufReader<R>,
+    buf: Vec<u8>,
+}
+
+// Iterator over back-to-back RESP frames, such as the replies to pipelined commands. Every frame
+// is deserialised into a T. Iteration stops at the end of the input or after the first error.
+pub struct StreamDeserializer<'de, R, T> {
+    read: R,
+    offset: usize,
+    failed: bool,
+    options: DeserializerOptions,
+    output: PhantomData<T>,
+    lifetime: PhantomData<&'de ()>,
+}
+
+impl<'de, R, T> StreamDeserializer<'de, R, T> {
+    fn new(read: R) -> Self {
+        StreamDeserializer {
+            read,
+            offset: 0,
+            failed: false,
+            options: DeserializerOptions::new(),
+            output: PhantomData,
+            lifetime: PhantomData,
+        }
+    }
+
+    pub fn with_options(mut self, options: DeserializerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Number of bytes consumed by the frames deserialised so far.
+    pub fn byte_offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'de, T> StreamDeserializer<'de, SliceRead<'de>, T> where T: Deserialize<'de> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &'de str) -> Self {
+        StreamDeserializer::from_slice(input.as_bytes())
+    }
+
+    pub fn from_slice(input: &'de [u8]) -> Self {
+        StreamDeserializer::new(SliceRead { slice: input })
+    }
+}
+
+impl<'de, R, T> StreamDeserializer<'de, IoRead<R>, T> where R: io::Read, T: DeserializeOwned {
+    pub fn from_reader(reader: R) -> Self {
+        StreamDeserializer::new(IoRead { reader: BufReader::new(reader), buf: Vec::new() })
+    }
+}
+
+impl<'de, T> Iterator for StreamDeserializer<'de, SliceRead<'de>, T> where T: Deserialize<'de> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed || self.offset == self.read.slice.len() {
+            return None;
+        }
+        let mut deserializer = Deserializer::from_slice(&self.read.slice[self.offset..]).with_options(self.options);
+        match T::deserialize(&mut deserializer) {
+            Ok(value) => {
+                self.offset += deserializer.pos;
+                Some(Ok(value))
+            }
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<'de, R, T> Iterator for StreamDeserializer<'de, IoRead<R>, T> where R: io::Read, T: DeserializeOwned {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed {
+            return None;
+        }
+        let result = match self.read.reader.fill_buf() {
+            Ok(&[]) => return None,
+            Ok(_) => {
+                self.read.buf.clear();
+                read_frame(&mut self.read.reader, &mut self.read.buf, &self.options)
+                    .and_then(|_| deserialize_frame(&self.read.buf, self.options))
+            }
+            Err(err) => Err(err.into()),
+        };
+        match result {
+            Ok(value) => {
+                self.offset += self.read.buf.len();
+                Some(Ok(value))
+            }
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+// Append exactly one RESP frame from `reader` to `buf`. Only the framing and the limits are
+// checked here, the content is validated when the buffer is deserialised.
+fn read_frame<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>, options: &DeserializerOptions) -> Result<()> {
+    let frame_start = buf.len();
+    // Values still to read at every nesting level.
+    let mut pending = vec![Pending::Count(1)];
+    while let Some(top) = pending.pop() {
+        match top {
+            Pending::Count(0) => continue,
+            Pending::Count(remaining) => pending.push(Pending::Count(remaining - 1)),
+            streamed => pending.push(streamed),
+        }
+
+        // Read at most one byte past the limit, enough to tell that it is exceeded.
+        let budget = options.max_total_bytes.saturating_sub(buf.len() - frame_start);
+        let start = buf.len();
+        // Reject a bad type marker upfront rather than waiting for the end of its line.
+        match reader.fill_buf()?.first() {
+            None => return Err(incomplete(None)),
+            Some(&marker) if !b"+-:$*#,(_=!~>%|.".contains(&marker) => {
+                return Err(ErrorKind::DeError(format!("invalid type marker: '{}'", marker as char)).into());
+            }
+            Some(_) => {}
+        }
+        // A line is its type marker, at most max_inline_len bytes and "\r\n".
+        let line_budget = budget.min(options.max_inline_len.saturating_add(3));
+        reader.by_ref().take((line_budget as u64).saturating_add(1)).read_until(b'\n', buf)?;
+        options.check_total_bytes(buf.len() - frame_start)?;
+        let end = buf[start + 1..].iter().position(|&b| b == b'\r');
+        options.check_inline_len(end.unwrap_or(buf.len() - start - 1))?;
+        if let Some(i) = end {
+            if start + i + 2 < buf.len() && buf[start + i + 2] != b'\n' {
+                return Err(ErrorKind::DeError("expected '\\n' after '\\r'".to_owned()).into());
+            }
+        }
+        if !buf.ends_with(b"\n") {
+            let needed = if buf.ends_with(b"\r") { 1 } else { 2 };
+            return Err(incomplete(Some(needed)));
+        }
+        if !buf[start..].ends_with(b"\r\n") {
+            return Err(ErrorKind::DeError("expected '\\r' before '\\n'".to_owned()).into());
+        }
+        // A line holds at least its type marker and "\r\n".
+        if buf.len() - start < 3 {
+            return Err(ErrorKind::DeError("missing type marker".to_owned()).into());
+        }
+        let line = &buf[start + 1..buf.len() - 2];
+        // Neither the end of a stream nor an attribute is a value of the stream.
+        if !matches!(buf[start], b'.' | b'|') {
+            if let Some(&mut Pending::Streamed { ref mut values, per_entry }) = pending.last_mut() {
+                *values += 1;
+                options.check_aggregate_len(values.div_ceil(per_entry))?;
+            }
+        }
+        match buf[start] {
+            b'+' | b'-' | b':' | b'#' | b',' | b'(' | b'_' => {}
+            b'$' | b'=' | b'!' => {
+                let len = parse_str::<i64>(line)?;
+                if len >= 0 {
+                    options.check_bulk_len(len as usize)?;
+                    let len = len as usize + 2;
+                    options.check_total_bytes((buf.len() - frame_start).saturating_add(len))?;
+                    let read = reader.by_ref().take(len as u64).read_to_end(buf)?;
+                    if read != len {
+                        return Err(incomplete(Some(len - read)));
+                    }
+                }
+            }
+            marker @ b'*' | marker @ b'~' | marker @ b'>' | marker @ b'%' | marker @ b'|' => {
+                // An attribute is followed by the value it describes, which is still to be read.
+                if marker == b'|' {
+                    if let Some(&mut Pending::Count(ref mut remaining)) = pending.last_mut() {
+                        *remaining += 1;
+                    }
+                }
+                let per_entry = if marker == b'%' || marker == b'|' { 2 } else { 1 };
+                if line == b"?" {
+                    options.check_depth(pending.len())?;
+                    pending.push(Pending::Streamed { values: 0, per_entry });
+                } else {
+                    let len = parse_str::<i64>(line)?;
+                    if len >= 0 {
+                        options.check_aggregate_len(len as usize)?;
+                        options.check_depth(pending.len())?;
+                        pending.push(Pending::Count((len as usize).saturating_mul(per_entry)));
+                    }
+                }
+            }
+            b'.' => {
+                if !matches!(pending.pop(), Some(Pending::Streamed { .. })) {
+                    return Err(ErrorKind::DeError("unexpected end of streamed aggregate".to_owned()).into());
+                }
+            }
+            marker => {
+                return Err(ErrorKind::DeError(format!("invalid type marker: '{}'", marker as char)).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Values still to read at one nesting level of a frame. The number of values of a streamed
+// aggregate is unknown up to ".\r\n", only those read so far are counted against the limit.
+enum Pending {
+    Count(usize),
+    Streamed { values: usize, per_entry: usize },
+}
+
+fn incomplete(needed: Option<usize>) -> Error {
+    ErrorKind::Incomplete(needed).into()
+}
+
+fn to_str(v: &[u8]) -> Result<&str> {
+    str::from_utf8(v).map_err(|_| ErrorKind::DeError("invalid UTF-8".to_owned()).into())
+}
+
+fn parse_str<T: FromStr>(v: &[u8]) -> Result<T> {
+    let s = to_str(v)?;
+    s.parse().map_err(|_| ErrorKind::DeError(format!("invalid number: '{}'", s)).into())
+}
+
+fn error_reply(v: &[u8]) -> Error {
+    ErrorKind::ErrorReply(RedisError::parse(&String::from_utf8_lossy(v))).into()
+}
+
+// Build the error for a token the visitor does not accept. An error reply is reported as such
+// rather than as a type mismatch.
+fn invalid_token(token: Token, expected: &dyn de::Expected) -> Error {
+    let unexpected = match token {
+        Token::Error(v) => return error_reply(v),
+        Token::SimpleString(v) | Token::BulkString(v) => match str::from_utf8(v) {
+            Ok(s) => Unexpected::Str(s),
+            Err(_) => Unexpected::Bytes(v),
+        },
+        Token::Integer(v) => Unexpected::Signed(v),
+        Token::Null => Unexpected::Unit,
+        Token::Boolean(v) => Unexpected::Bool(v),
+        Token::Double(v) => match str::from_utf8(v).ok().and_then(|s| s.parse().ok()) {
+            Some(v) => Unexpected::Float(v),
+            None => Unexpected::Bytes(v),
+        },
+        Token::BigNumber(_) => Unexpected::Other("big number"),
+        Token::Array(_) => Unexpected::Seq,
+        Token::Map(_) => Unexpected::Map,
+    };
+    de::Error::invalid_type(unexpected, expected)
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    // Deserialise based on the RESP type marker: strings are visited as str (or bytes when they
+    // are not valid UTF-8) borrowed from the input, integers as i64, null as unit, RESP3 booleans
+    // as bool, RESP3 doubles as f64, RESP3 big numbers as i128 or u128 (or str when they fit in
+    // neither), arrays and RESP3 sets as sequences, and RESP3 maps as maps.
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::SimpleString(v) => visitor.visit_borrowed_str(to_str(v)?),
+            Token::Error(v) => Err(error_reply(v)),
+            Token::Integer(v) => visitor.visit_i64(v),
+            Token::BulkString(v) => match str::from_utf8(v) {
+                Ok(s) => visitor.visit_borrowed_str(s),
+                Err(_) => visitor.visit_borrowed_bytes(v),
+            },
+            Token::Null => visitor.visit_unit(),
+            Token::Boolean(v) => visitor.visit_bool(v),
+            Token::Double(v) => visitor.visit_f64(parse_str(v)?),
+            Token::BigNumber(v) => {
+                let s = to_str(v)?;
+                if let Ok(n) = s.parse() {
+                    visitor.visit_i128(n)
+                } else if let Ok(n) = s.parse() {
+                    visitor.visit_u128(n)
+                } else {
+                    visitor.visit_borrowed_str(s)
+                }
+            }
+            Token::Array(len) => self.visit_array(len, visitor),
+            Token::Map(len) => self.visit_pairs(len, visitor),
+        }
+    }
+
+    // Deserialise from RESP bulk or simple strings "true" and "false" or "1" and "0", from RESP
+    // integers 1 and 0, or from RESP3 booleans "#t\r\n" and "#f\r\n". This covers every
+    // BoolEncoding of the serializer.
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Boolean(v) => visitor.visit_bool(v),
+            Token::SimpleString(v) | Token::BulkString(v) => match v {
+                b"true" | b"1" => visitor.visit_bool(true),
+                b"false" | b"0" => visitor.visit_bool(false),
+                _ => Err(invalid_token(Token::BulkString(v), &visitor)),
+            },
+            Token::Integer(1) => visitor.visit_bool(true),
+            Token::Integer(0) => visitor.visit_bool(false),
+            Token::Integer(v) => Err(de::Error::invalid_value(Unexpected::Signed(v), &visitor)),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    // Deserialise from RESP integers, or from bulk strings as written by the serializer, for
+    // example "$4\r\n-100\r\n".
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_signed(visitor)
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_signed(visitor)
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_signed(visitor)
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_signed(visitor)
+    }
+
+    // Deserialise from RESP integers, RESP3 big numbers, or from bulk strings as written by the
+    // serializer, for example "(-170141183460469231731687303715884105728\r\n".
+    fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Integer(v) => visitor.visit_i64(v),
+            Token::SimpleString(s) | Token::BulkString(s) | Token::BigNumber(s) => visitor.visit_i128(parse_str(s)?),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    // Deserialise from RESP integers, or from bulk strings as written by the serializer, for
+    // example "$3\r\n100\r\n".
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_unsigned(visitor)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_unsigned(visitor)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_unsigned(visitor)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_unsigned(visitor)
+    }
+
+    // Deserialise from RESP integers, RESP3 big numbers, or from bulk strings as written by the
+    // serializer, for example "(340282366920938463463374607431768211455\r\n".
+    fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Integer(v) => visitor.visit_i64(v),
+            Token::SimpleString(s) | Token::BulkString(s) | Token::BigNumber(s) => visitor.visit_u128(parse_str(s)?),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    // Deserialise from RESP integers, RESP3 doubles, or from bulk strings as written by the
+    // serializer, for example "$4\r\n1.34\r\n". Redis scores such as "+inf", "-inf" and "1e-5" are
+    // accepted.
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        // Parse as f32 directly, going through f64 may round differently.
+        match self.next_token()? {
+            Token::Integer(v) => visitor.visit_i64(v),
+            Token::SimpleString(s) | Token::BulkString(s) | Token::Double(s) => visitor.visit_f32(parse_str(s)?),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_float(visitor)
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_str(visitor)
+    }
+
+    // Deserialise from RESP bulk or simple strings, borrowing from the input. RESP3 big numbers are
+    // accepted too, as a fallback for values that do not fit in any integer type, and so are RESP3
+    // doubles, keeping their text as sent, such as "1.5" from ",1.5\r\n".
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::SimpleString(v) | Token::BulkString(v) | Token::BigNumber(v) | Token::Double(v) => {
+                visitor.visit_borrowed_str(to_str(v)?)
+            }
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_str(visitor)
+    }
+
+    // Deserialise from RESP bulk or simple strings, without requiring valid UTF-8. The bytes are
+    // borrowed from the input. RESP3 big numbers and doubles are accepted as for strings.
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::SimpleString(v) | Token::BulkString(v) | Token::BigNumber(v) | Token::Double(v) => {
+                visitor.visit_borrowed_bytes(v)
+            }
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    // Null bulk string "$-1\r\n" and null array "*-1\r\n" are None, anything else is Some.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        let start = self.pos;
+        match self.next_token()? {
+            Token::Null => visitor.visit_none(),
+            _ => {
+                self.pos = start;
+                visitor.visit_some(self)
+            }
+        }
+    }
+
+    // Deserialise from RESP null bulk string or null array, or from RESP3 null "_\r\n".
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Null => visitor.visit_unit(),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    // A Reply is visited as str when the next value is an error reply, simple or RESP3 blob error,
+    // after any attributes.
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        if name == REPLY_TOKEN {
+            self.skip_attributes()?;
+            if matches!(self.peek_byte()?, b'-' | b'!') {
+                if let Token::Error(v) = self.next_token()? {
+                    return visitor.visit_str(&String::from_utf8_lossy(v));
+                }
+            }
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    // Deserialise from RESP array, or from RESP3 set or push. A RESP3 map is visited as alternating
+    // keys and values, so the reply to HGETALL reads the same in RESP2 and RESP3.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Array(len) => self.visit_array(len, visitor),
+            Token::Map(len) => self.visit_array(len.map(|len| len.saturating_mul(2)), visitor),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(self, _name: &'static str, _len: usize, visitor: V)
+        -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    // Deserialise from RESP array of alternating keys and values, such as the reply to HGETALL.
+    // For example "*4\r\n$4\r\nname\r\n$3\r\nfoo\r\n$3\r\nage\r\n$2\r\n42\r\n", or from RESP3 map
+    // "%2\r\n$4\r\nname\r\n$3\r\nfoo\r\n$3\r\nage\r\n$2\r\n42\r\n".
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::Array(Some(len)) if len % 2 == 0 => self.visit_pairs(Some(len / 2), visitor),
+            Token::Array(Some(len)) => Err(de::Error::invalid_length(len, &"an even number of elements")),
+            Token::Array(None) => self.visit_pairs(None, visitor),
+            Token::Map(len) => self.visit_pairs(len, visitor),
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    // Deserialise from RESP array of alternating field names and values, or from RESP3 map.
+    // Unknown fields are skipped unless the struct denies them.
+    fn deserialize_struct<V>(self, _name: &'static str, _fields: &'static [&'static str], visitor: V)
+        -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_map(visitor)
+    }
+
+    // Deserialise unit variants from RESP bulk or simple string of the variant name, and other
+    // variants from RESP array of the variant name and the value, as written by the serializer.
+    fn deserialize_enum<V>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V)
+        -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.next_token()? {
+            Token::SimpleString(v) | Token::BulkString(v) => visitor.visit_enum(to_str(v)?.into_deserializer()),
+            Token::Array(Some(2)) => {
+                self.enter()?;
+                let value = visitor.visit_enum(Enum { de: &mut *self })?;
+                self.depth -= 1;
+                Ok(value)
+            }
+            Token::Array(len) => {
+                Err(de::Error::invalid_length(len.unwrap_or(0), &"an array of the variant name and the value"))
+            }
+            token => Err(invalid_token(token, &visitor)),
+        }
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.deserialize_str(visitor)
+    }
+
+    // Skip the next value whatever its type, including error replies.
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        self.skip_value()?;
+        visitor.visit_unit()
+    }
+}
+
+const REPLY_TOKEN: &str = "$serde_resp::private::Reply";
+
+// A reply that is either a T or an error reply, for example the result of every command in a
+// pipeline. Error replies become Err values instead of failing the whole deserialisation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply<T>(pub ::std::result::Result<T, RedisError>);
+
+impl<T> Reply<T> {
+    pub fn into_result(self) -> ::std::result::Result<T, RedisError> {
+        self.0
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Reply<T> where T: Deserialize<'de> {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+        where D: de::Deserializer<'de>
+    {
+        struct ReplyVisitor<T>(PhantomData<T>);
+
+        impl<'de, T> Visitor<'de> for ReplyVisitor<T> where T: Deserialize<'de> {
+            type Value = Reply<T>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a RESP reply")
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> ::std::result::Result<Self::Value, D::Error>
+                where D: de::Deserializer<'de>
+            {
+                T::deserialize(deserializer).map(|value| Reply(Ok(value)))
+            }
+
+            fn visit_str<E>(self, v: &str) -> ::std::result::Result<Self::Value, E>
+                where E: de::Error
+            {
+                Ok(Reply(Err(RedisError::parse(v))))
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(REPLY_TOKEN, ReplyVisitor(PhantomData))
+    }
+}
+
+// Access to the elements of an array. `remaining` is None until the end of a streamed array.
+struct Array<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    remaining: Option<usize>,
+    read: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for Array<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+        where T: DeserializeSeed<'de>
+    {
+        if !next_entry(self.de, &mut self.remaining, &mut self.read)? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+// Count down one more entry of an aggregate, telling whether there is one. The entries of a
+// streamed aggregate are counted in `read` instead, against max_aggregate_len.
+fn next_entry(de: &mut Deserializer, remaining: &mut Option<usize>, read: &mut usize) -> Result<bool> {
+    match *remaining {
+        Some(0) => Ok(false),
+        Some(ref mut n) => {
+            *n -= 1;
+            Ok(true)
+        }
+        None if de.read_stream_end()? => {
+            *remaining = Some(0);
+            Ok(false)
+        }
+        None => {
+            *read += 1;
+            de.options.check_aggregate_len(*read)?;
+            Ok(true)
+        }
+    }
+}
+
+// Access to the entries of a map. `remaining` is None until the end of a streamed map.
+struct Pairs<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    remaining: Option<usize>,
+    read: usize,
+}
+
+impl<'de, 'a> MapAccess<'de> for Pairs<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where K: DeserializeSeed<'de>
+    {
+        if !next_entry(self.de, &mut self.remaining, &mut self.read)? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where V: DeserializeSeed<'de>
+    {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+// Access to an enum encoded as an array of the variant name and the value.
+struct Enum<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for Enum<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+        where V: DeserializeSeed<'de>
+    {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for Enum<'a, 'de> {
+    type Error = Error;
+
+    // A unit variant in the array form carries null.
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(self.de)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+        where T: DeserializeSeed<'de>
+    {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
+
+
+///////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ser::{to_string, to_vec, SimpleString};
+    use serde_bytes::{ByteBuf, Bytes};
+    use serde::Serialize;
+    use std::fmt::Debug;
+    use std::borrow::Cow;
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::BufReader;
+
+    #[test]
+    fn test_deserialize_simple_string() {
+        assert_eq!(from_str::<String>("+OK\r\n").unwrap(), "OK");
+        assert_eq!(from_str::<String>("+\r\n").unwrap(), "");
+    }
+
+    #[test]
+    fn test_deserialize_error() {
+        let err = from_str::<String>("-ERR unknown command\r\n").unwrap_err();
+        assert_eq!(err.to_string(), "error reply: 'ERR unknown command'");
+        match *err.kind() {
+            ErrorKind::ErrorReply(ref e) => assert_eq!(*e, RedisError::new("ERR", "unknown command")),
+            _ => panic!("expected error reply"),
+        }
+        assert!(from_str::<Vec<i64>>("*2\r\n:1\r\n-ERR\r\n").is_err());
+        from_str::<::serde::de::IgnoredAny>("-ERR\r\n").unwrap();
+    }
+
+    #[test]
+    fn test_deserialize_reply() {
+        assert_eq!(from_str::<Reply<String>>("+OK\r\n").unwrap(), Reply(Ok("OK".to_owned())));
+        assert_eq!(from_str::<Reply<Option<i64>>>("$-1\r\n").unwrap(), Reply(Ok(None)));
+        assert_eq!(
+            from_str::<Reply<i64>>("-WRONGTYPE Operation against a key holding the wrong kind of value\r\n").unwrap(),
+            Reply(Err(RedisError::new("WRONGTYPE", "Operation against a key holding the wrong kind of value")))
+        );
+        assert_eq!(from_str::<Reply<i64>>("-ERR\r\n").unwrap().into_result(), Err(RedisError::new("ERR", "")));
+        assert!(from_str::<Reply<i64>>("+OK\r\n").is_err());
+
+        let replies = from_str::<Vec<Reply<i64>>>("*3\r\n:1\r\n-ERR value is not an integer\r\n:3\r\n").unwrap();
+        assert_eq!(replies, vec![
+            Reply(Ok(1)),
+            Reply(Err(RedisError::new("ERR", "value is not an integer"))),
+            Reply(Ok(3)),
+        ]);
+    }
+
+    #[test]
+    fn test_deserialize_integer() {
+        assert_eq!(from_str::<i64>(":1000\r\n").unwrap(), 1000);
+        assert_eq!(from_str::<i64>(":-1\r\n").unwrap(), -1);
+        assert_eq!(from_str::<u8>(":100\r\n").unwrap(), 100);
+        assert!(from_str::<u8>(":-1\r\n").is_err());
+        assert!(from_str::<i64>(":abc\r\n").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_bulk_string() {
+        assert_eq!(from_str::<String>("$6\r\nfoobar\r\n").unwrap(), "foobar");
+        assert_eq!(from_str::<String>("$0\r\n\r\n").unwrap(), "");
+        assert_eq!(from_str::<String>("$4\r\nfo\r\n\r\n").unwrap(), "fo\r\n");
+        assert!(from_str::<String>("$6\r\nfoo\r\n").is_err());
+        assert!(from_str::<String>("$3\r\nfoobar\r\n").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_null() {
+        assert_eq!(from_str::<Option<String>>("$-1\r\n").unwrap(), None);
+        assert_eq!(from_str::<Option<String>>("*-1\r\n").unwrap(), None);
+        assert_eq!(from_str::<Option<String>>("$3\r\nfoo\r\n").unwrap(), Some("foo".to_owned()));
+        from_str::<()>("$-1\r\n").unwrap();
+        from_str::<()>("*-1\r\n").unwrap();
+    }
+
+    #[test]
+    fn test_deserialize_array() {
+        assert_eq!(from_str::<Vec<String>>("*0\r\n").unwrap(), Vec::<String>::new());
+        assert_eq!(from_str::<Vec<String>>("*2\r\n$3\r\nfoo\r\n+bar\r\n").unwrap(), vec!["foo", "bar"]);
+        assert_eq!(from_str::<Vec<i64>>("*3\r\n:1\r\n:2\r\n:3\r\n").unwrap(), vec![1, 2, 3]);
+        assert_eq!(from_str::<(String, i64)>("*2\r\n$5\r\nmykey\r\n:10\r\n").unwrap(), ("mykey".to_owned(), 10));
+        assert!(from_str::<(String, i64)>("*3\r\n$5\r\nmykey\r\n:10\r\n:11\r\n").is_err());
+        assert!(from_str::<Vec<i64>>("*3\r\n:1\r\n:2\r\n").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        assert!(from_str::<String>("").is_err());
+        assert!(from_str::<String>("foobar\r\n").is_err());
+        assert!(from_str::<String>("+OK").is_err());
+        assert!(from_str::<String>("+OK\r\n+OK\r\n").is_err());
+        assert!(from_str::<String>("$-2\r\n").is_err());
+    }
+
+    fn round_trip<T>(value: T) where T: Serialize + DeserializeOwned + PartialEq + Debug {
+        assert_eq!(from_str::<T>(&to_string(&value).unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_deserialize_number_forms() {
+        assert_eq!(from_str::<i64>("$4\r\n-100\r\n").unwrap(), -100);
+        assert_eq!(from_str::<i64>("+-100\r\n").unwrap(), -100);
+        assert_eq!(from_str::<i64>(":-100\r\n").unwrap(), -100);
+        assert_eq!(from_str::<u64>("$20\r\n18446744073709551615\r\n").unwrap(), u64::MAX);
+        assert_eq!(from_str::<u8>(":255\r\n").unwrap(), 255);
+        assert!(from_str::<u8>("$3\r\n256\r\n").is_err());
+        assert!(from_str::<i8>(":-129\r\n").is_err());
+        assert!(from_str::<i64>("$4\r\n1.34\r\n").is_err());
+
+        assert_eq!(from_str::<f64>("$4\r\n1.34\r\n").unwrap(), 1.34);
+        assert_eq!(from_str::<f64>(":10\r\n").unwrap(), 10.0);
+        assert_eq!(from_str::<f32>("$4\r\n1.34\r\n").unwrap(), 1.34);
+        assert!(from_str::<f64>("$3\r\nabc\r\n").is_err());
+
+        assert!(from_str::<bool>("$4\r\ntrue\r\n").unwrap());
+        assert!(!from_str::<bool>("+false\r\n").unwrap());
+        assert!(from_str::<bool>(":1\r\n").unwrap());
+        assert!(!from_str::<bool>(":0\r\n").unwrap());
+        assert!(from_str::<bool>(":2\r\n").is_err());
+        assert!(from_str::<bool>("$3\r\nyes\r\n").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_bool() {
+        round_trip(true);
+        round_trip(false);
+    }
+
+    #[test]
+    fn test_round_trip_int() {
+        round_trip(-100_i8);
+        round_trip(100_i8);
+        round_trip(-100_i16);
+        round_trip(100_i16);
+        round_trip(-100_i32);
+        round_trip(100_i32);
+        round_trip(-100_i64);
+        round_trip(100_i64);
+
+        round_trip(100_u8);
+        round_trip(100_u16);
+        round_trip(100_u32);
+        round_trip(100_u64);
+    }
+
+    #[test]
+    fn test_round_trip_float() {
+        round_trip(-1.34_f32);
+        round_trip(1.34_f32);
+        round_trip(2.75_f32);
+        round_trip(-1.34_f64);
+        round_trip(1.34_f64);
+        round_trip(2.75_f64);
+    }
+
+    #[test]
+    fn test_round_trip_char() {
+        round_trip('a');
+    }
+
+    #[test]
+    fn test_round_trip_str() {
+        round_trip("".to_owned());
+        round_trip("foobar".to_owned());
+        assert_eq!(from_str::<&str>(&to_string(&"foobar").unwrap()).unwrap(), "foobar");
+    }
+
+    #[test]
+    fn test_round_trip_seq() {
+        round_trip(Vec::new() as Vec<bool>);
+        round_trip(vec![true, false]);
+
+        round_trip(Vec::new() as Vec<i8>);
+        round_trip(vec![1_i8, 2_i8]);
+        round_trip(Vec::new() as Vec<i16>);
+        round_trip(vec![1_i16, 2_i16]);
+        round_trip(Vec::new() as Vec<i32>);
+        round_trip(vec![1_i32, 2_i32]);
+        round_trip(Vec::new() as Vec<i64>);
+        round_trip(vec![1_i64, 2_i64]);
+
+        round_trip(Vec::new() as Vec<u8>);
+        round_trip(vec![1_u8, 2_u8]);
+        round_trip(Vec::new() as Vec<u16>);
+        round_trip(vec![1_u16, 2_u16]);
+        round_trip(Vec::new() as Vec<u32>);
+        round_trip(vec![1_u32, 2_u32]);
+        round_trip(Vec::new() as Vec<u64>);
+        round_trip(vec![1_u64, 2_u64]);
+
+        round_trip(Vec::new() as Vec<f32>);
+        round_trip(vec![1_f32, 2.14_f32]);
+        round_trip(Vec::new() as Vec<f64>);
+        round_trip(vec![1_f64, 2.14_f64]);
+
+        round_trip(Vec::new() as Vec<char>);
+        round_trip(vec!['a', 'b']);
+
+        round_trip(Vec::new() as Vec<Vec<char>>);
+        round_trip(vec![vec!['a'], vec!['b', 'c']]);
+    }
+
+    #[test]
+    fn test_round_trip_tuple() {
+        round_trip(("mykey".to_owned(), 10));
+        round_trip(("mykey".to_owned(), vec!['a', 'b']));
+        round_trip(("mykey".to_owned(), (10, 'a')));
+    }
+
+    #[test]
+    fn test_round_trip_tuple_struct() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Tuple<'a, T>(
+            &'a str,
+            T
+        );
+
+        let s = to_string(&Tuple("mykey", 10)).unwrap();
+        assert_eq!(from_str::<Tuple<i32>>(&s).unwrap(), Tuple("mykey", 10));
+        let s = to_string(&Tuple("mykey", vec!['a', 'b'])).unwrap();
+        assert_eq!(from_str::<Tuple<Vec<char>>>(&s).unwrap(), Tuple("mykey", vec!['a', 'b']));
+        let s = to_string(&Tuple("mykey", (10, 'a'))).unwrap();
+        assert_eq!(from_str::<Tuple<(i32, char)>>(&s).unwrap(), Tuple("mykey", (10, 'a')));
+    }
+
+    #[test]
+    fn test_round_trip_unit() {
+        round_trip(());
+    }
+
+    #[test]
+    fn test_round_trip_none() {
+        round_trip(None as Option<bool>);
+        round_trip(None as Option<char>);
+        round_trip(None as Option<i8>);
+        round_trip(None as Option<i16>);
+        round_trip(None as Option<i32>);
+        round_trip(None as Option<i64>);
+        round_trip(None as Option<u8>);
+        round_trip(None as Option<u16>);
+        round_trip(None as Option<u32>);
+        round_trip(None as Option<u64>);
+        round_trip(None as Option<f32>);
+        round_trip(None as Option<f64>);
+        round_trip(None as Option<String>);
+    }
+
+    #[test]
+    fn test_round_trip_some() {
+        round_trip(Some(true));
+        round_trip(Some(-1_i8));
+        round_trip(Some(-1_i16));
+        round_trip(Some(-100_i32));
+        round_trip(Some(-100_i64));
+        round_trip(Some(1_u8));
+        round_trip(Some(1_u16));
+        round_trip(Some(100_u32));
+        round_trip(Some(100_u64));
+        round_trip(Some(-1.34_f32));
+        round_trip(Some(-1.34_f64));
+        round_trip(Some('a'));
+        round_trip(Some("foobar".to_owned()));
+        round_trip(Some(vec![vec!['a'], vec!['b', 'c']]));
+        round_trip(Some(("mykey".to_owned(), (10, 'a'))));
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Tuple<'a, T>(
+            &'a str,
+            T
+        );
+        let s = to_string(&Some(Tuple("mykey", (10, 'a')))).unwrap();
+        assert_eq!(from_str::<Option<Tuple<(i32, char)>>>(&s).unwrap(), Some(Tuple("mykey", (10, 'a'))));
+    }
+
+    #[test]
+    fn test_deserialize_from_slice() {
+        assert_eq!(from_slice::<String>(b"$6\r\nfoobar\r\n").unwrap(), "foobar");
+        assert!(from_slice::<String>(b"$2\r\n\xff\xfe\r\n").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_borrowed() {
+        let input = b"$6\r\nfoobar\r\n";
+        let s = from_slice::<&str>(input).unwrap();
+        assert_eq!(s, "foobar");
+        assert_eq!(s.as_ptr(), input[4..].as_ptr());
+
+        let b = from_slice::<&[u8]>(b"$3\r\n\x00\xff\x01\r\n").unwrap();
+        assert_eq!(b, b"\x00\xff\x01");
+
+        assert_eq!(from_str::<&str>("+OK\r\n").unwrap(), "OK");
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Tuple<'a>(
+            &'a str,
+            #[serde(borrow)]
+            Cow<'a, str>,
+            &'a [u8]
+        );
+
+        let t = from_slice::<Tuple>(b"*3\r\n$5\r\nmykey\r\n$3\r\nfoo\r\n$3\r\nbar\r\n").unwrap();
+        assert_eq!(t, Tuple("mykey", Cow::Borrowed("foo"), b"bar"));
+        match t.1 {
+            Cow::Borrowed(_) => {}
+            Cow::Owned(_) => panic!("expected borrowed string"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_from_reader() {
+        let mut reader = BufReader::new(&b"*2\r\n$3\r\nfoo\r\n:10\r\n+OK\r\n$-1\r\n*-1\r\n$3\r\nbar"[..]);
+        assert_eq!(from_reader::<_, (String, i64)>(&mut reader).unwrap(), ("foo".to_owned(), 10));
+        assert_eq!(from_reader::<_, String>(&mut reader).unwrap(), "OK");
+        assert_eq!(from_reader::<_, Option<String>>(&mut reader).unwrap(), None);
+        assert_eq!(from_reader::<_, Option<Vec<String>>>(&mut reader).unwrap(), None);
+        assert!(from_reader::<_, String>(&mut reader).is_err());
+
+        let mut reader = &b"$4\r\nfo\r\n\r\n*0\r\n"[..];
+        assert_eq!(from_reader::<_, String>(&mut reader).unwrap(), "fo\r\n");
+        assert_eq!(reader, b"*0\r\n");
+
+        assert!(from_reader::<_, String>(&b""[..]).is_err());
+        assert!(from_reader::<_, String>(&b"+OK\n"[..]).is_err());
+        assert!(from_reader::<_, String>(&b"?OK\r\n"[..]).is_err());
+        assert!(from_reader::<_, Vec<i64>>(&b"*2\r\n:1\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_stream_deserializer() {
+        let input = "+OK\r\n:10\r\n*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$-1\r\n";
+
+        let mut stream = StreamDeserializer::<_, Option<&str>>::from_str("+OK\r\n$3\r\nfoo\r\n$-1\r\n");
+        assert_eq!(stream.byte_offset(), 0);
+        assert_eq!(stream.next().unwrap().unwrap(), Some("OK"));
+        assert_eq!(stream.byte_offset(), 5);
+        assert_eq!(stream.next().unwrap().unwrap(), Some("foo"));
+        assert_eq!(stream.byte_offset(), 14);
+        assert_eq!(stream.next().unwrap().unwrap(), None);
+        assert_eq!(stream.byte_offset(), 19);
+        assert!(stream.next().is_none());
+
+        let mut stream = StreamDeserializer::<_, i64>::from_slice(input.as_bytes());
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+        assert_eq!(stream.byte_offset(), 0);
+
+        let mut stream = StreamDeserializer::<_, Option<String>>::from_reader(&b"+OK\r\n$3\r\nfoo\r\n$-1\r\n"[..]);
+        assert_eq!(stream.next().unwrap().unwrap(), Some("OK".to_owned()));
+        assert_eq!(stream.byte_offset(), 5);
+        assert_eq!(stream.next().unwrap().unwrap(), Some("foo".to_owned()));
+        assert_eq!(stream.byte_offset(), 14);
+        assert_eq!(stream.next().unwrap().unwrap(), None);
+        assert_eq!(stream.byte_offset(), 19);
+        assert!(stream.next().is_none());
+
+        let stream = StreamDeserializer::<_, ::serde::de::IgnoredAny>::from_reader(input.as_bytes());
+        assert_eq!(stream.count(), 4);
+
+        let mut stream = StreamDeserializer::<_, String>::from_reader(&b"+OK\r\n$3\r\nfo"[..]);
+        assert_eq!(stream.next().unwrap().unwrap(), "OK");
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+        assert_eq!(stream.byte_offset(), 5);
+    }
+
+    #[test]
+    fn test_deserialize_incomplete() {
+        fn needed(input: &[u8]) -> Option<usize> {
+            let err = from_slice::<Vec<Option<String>>>(input).unwrap_err();
+            assert!(err.is_incomplete());
+            match *err.kind() {
+                ErrorKind::Incomplete(needed) => needed,
+                _ => unreachable!(),
+            }
+        }
+
+        assert_eq!(needed(b""), None);
+        assert_eq!(needed(b"*"), Some(2));
+        assert_eq!(needed(b"*2\r"), Some(1));
+        assert_eq!(needed(b"*2\r\n"), None);
+        assert_eq!(needed(b"*2\r\n$6\r\nfoo"), Some(5));
+        assert_eq!(needed(b"*2\r\n$6\r\nfoobar\r"), Some(1));
+        assert_eq!(needed(b"*2\r\n$6\r\nfoobar\r\n$-"), Some(2));
+
+        let err = from_reader::<_, String>(&b"$6\r\nfoo"[..]).unwrap_err();
+        assert!(err.is_incomplete());
+        assert_eq!(err.to_string(), "incomplete frame: at least 5 more bytes needed");
+        assert!(from_reader::<_, String>(&b""[..]).unwrap_err().is_incomplete());
+        assert!(from_reader::<_, String>(&b"+OK\r"[..]).unwrap_err().is_incomplete());
+
+        assert!(!from_slice::<String>(b"$3\r\nfoobar\r\n").unwrap_err().is_incomplete());
+        assert!(!from_slice::<String>(b"?\r\n").unwrap_err().is_incomplete());
+        assert!(!from_reader::<_, String>(&b"+OK\n"[..]).unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_deserialize_map() {
+        let m = from_str::<BTreeMap<String, i64>>("*4\r\n$3\r\nfoo\r\n:1\r\n$3\r\nbar\r\n$1\r\n2\r\n").unwrap();
+        assert_eq!(m, vec![("foo".to_owned(), 1), ("bar".to_owned(), 2)].into_iter().collect());
+
+        let m = from_str::<HashMap<&str, &str>>("*4\r\n$9\r\nmaxmemory\r\n$1\r\n0\r\n$7\r\ntimeout\r\n$3\r\n300\r\n").unwrap();
+        assert_eq!(m.len(), 2);
+        assert_eq!(m["maxmemory"], "0");
+        assert_eq!(m["timeout"], "300");
+
+        assert!(from_str::<HashMap<String, String>>("*0\r\n").unwrap().is_empty());
+        assert!(from_str::<HashMap<String, String>>("*3\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$3\r\nbaz\r\n").is_err());
+        assert!(from_str::<HashMap<String, String>>("$3\r\nfoo\r\n").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_struct() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct User {
+            name: String,
+            age: u32,
+        }
+
+        let input = "*4\r\n$4\r\nname\r\n$3\r\nfoo\r\n$3\r\nage\r\n$2\r\n42\r\n";
+        assert_eq!(from_str::<User>(input).unwrap(), User { name: "foo".to_owned(), age: 42 });
+
+        let input = "*4\r\n$3\r\nage\r\n:42\r\n$4\r\nname\r\n+foo\r\n";
+        assert_eq!(from_str::<User>(input).unwrap(), User { name: "foo".to_owned(), age: 42 });
+
+        // unknown fields are ignored, including nested arrays
+        let input = "*6\r\n$4\r\nname\r\n$3\r\nfoo\r\n$4\r\ntags\r\n*2\r\n$1\r\na\r\n$1\r\nb\r\n$3\r\nage\r\n$2\r\n42\r\n";
+        assert_eq!(from_str::<User>(input).unwrap(), User { name: "foo".to_owned(), age: 42 });
+
+        // missing fields
+        assert!(from_str::<User>("*2\r\n$4\r\nname\r\n$3\r\nfoo\r\n").is_err());
+        assert!(from_str::<User>("*3\r\n$4\r\nname\r\n$3\r\nfoo\r\n$3\r\nage\r\n").is_err());
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(deny_unknown_fields)]
+        struct Strict {
+            name: String,
+            #[serde(default)]
+            age: u32,
+            email: Option<String>,
+        }
+
+        assert_eq!(from_str::<Strict>("*2\r\n$4\r\nname\r\n$3\r\nfoo\r\n").unwrap(),
+                   Strict { name: "foo".to_owned(), age: 0, email: None });
+        assert!(from_str::<Strict>(input).is_err());
+    }
+
+    #[test]
+    fn test_deserializer_options() {
+        fn deserialize<T: DeserializeOwned>(input: &[u8], options: DeserializerOptions) -> (Result<T>, Result<T>) {
+            let mut deserializer = Deserializer::from_slice(input).with_options(options);
+            let from_slice = T::deserialize(&mut deserializer);
+            (from_slice, from_reader_with_options(input, options))
+        }
+
+        fn assert_limit<T: DeserializeOwned + Debug>(input: &[u8], options: DeserializerOptions, kind: ErrorKind) {
+            let (from_slice, from_reader) = deserialize::<T>(input, options);
+            assert_eq!(from_slice.unwrap_err().kind().to_string(), kind.to_string());
+            assert_eq!(from_reader.unwrap_err().kind().to_string(), kind.to_string());
+        }
+
+        let options = DeserializerOptions::new().max_depth(2);
+        let (a, b) = deserialize::<Vec<Vec<i64>>>(b"*1\r\n*1\r\n:1\r\n", options);
+        assert_eq!(a.unwrap(), vec![vec![1]]);
+        assert_eq!(b.unwrap(), vec![vec![1]]);
+        assert_limit::<Vec<Vec<Vec<i64>>>>(b"*1\r\n*1\r\n*0\r\n", options, ErrorKind::DepthLimitExceeded(2));
+        assert_limit::<::serde::de::IgnoredAny>(b"*1\r\n*1\r\n*0\r\n", options, ErrorKind::DepthLimitExceeded(2));
+
+        let options = DeserializerOptions::new().max_bulk_len(3);
+        let (a, b) = deserialize::<String>(b"$3\r\nfoo\r\n", options);
+        assert_eq!(a.unwrap(), "foo");
+        assert_eq!(b.unwrap(), "foo");
+        assert_limit::<String>(b"$6\r\nfoobar\r\n", options, ErrorKind::BulkLengthLimitExceeded(3));
+        assert_limit::<String>(b"$999999999\r\n", options, ErrorKind::BulkLengthLimitExceeded(3));
+
+        let options = DeserializerOptions::new().max_aggregate_len(2);
+        let (a, b) = deserialize::<Vec<i64>>(b"*2\r\n:1\r\n:2\r\n", options);
+        assert_eq!(a.unwrap(), vec![1, 2]);
+        assert_eq!(b.unwrap(), vec![1, 2]);
+        assert_limit::<Vec<i64>>(b"*2147483647\r\n", options, ErrorKind::AggregateLengthLimitExceeded(2));
+
+        let options = DeserializerOptions::new().max_total_bytes(12);
+        let (a, b) = deserialize::<Vec<i64>>(b"*2\r\n:1\r\n:2\r\n", options);
+        assert_eq!(a.unwrap(), vec![1, 2]);
+        assert_eq!(b.unwrap(), vec![1, 2]);
+        assert_limit::<Vec<i64>>(b"*2\r\n:1\r\n:20\r\n", options, ErrorKind::TotalBytesLimitExceeded(12));
+        assert_limit::<String>(b"$20\r\nfoo", options, ErrorKind::TotalBytesLimitExceeded(12));
+        assert_limit::<String>(b"+foobarfoobarfoobar", options, ErrorKind::TotalBytesLimitExceeded(12));
+
+        let options = DeserializerOptions::new().max_inline_len(3);
+        let (a, b) = deserialize::<Vec<String>>(b"*2\r\n+foo\r\n$6\r\nfoobar\r\n", options);
+        assert_eq!(a.unwrap(), vec!["foo", "foobar"]);
+        assert_eq!(b.unwrap(), vec!["foo", "foobar"]);
+        assert_limit::<String>(b"+foob\r\n", options, ErrorKind::InlineLengthLimitExceeded(3));
+        assert_limit::<String>(b"+foob", options, ErrorKind::InlineLengthLimitExceeded(3));
+        assert_limit::<String>(b"$1000\r\n", options, ErrorKind::InlineLengthLimitExceeded(3));
+
+        // the default inline limit is 64 KB, whatever the size of the frame
+        let mut line = b"+".to_vec();
+        line.extend(vec![b'a'; 65536]);
+        assert!(from_slice::<String>(&line).unwrap_err().is_incomplete());
+        line.push(b'a');
+        assert_limit::<String>(&line, DeserializerOptions::new(), ErrorKind::InlineLengthLimitExceeded(65536));
+        line.extend(vec![b'a'; 50 * 1024 * 1024]);
+        assert_limit::<String>(&line, DeserializerOptions::new(), ErrorKind::InlineLengthLimitExceeded(65536));
+
+        // the default bulk limit is 512 MB
+        assert_limit::<String>(b"$536870913\r\n", DeserializerOptions::new(), ErrorKind::BulkLengthLimitExceeded(536870912));
+        assert!(from_slice::<String>(b"$536870912\r\n").unwrap_err().is_incomplete());
+
+        // the default aggregate limit is 16M elements
+        assert_limit::<Vec<i64>>(b"*2147483647\r\n", DeserializerOptions::new(), ErrorKind::AggregateLengthLimitExceeded(16777216));
+        assert_limit::<Vec<i64>>(b"*16777217\r\n", DeserializerOptions::new(), ErrorKind::AggregateLengthLimitExceeded(16777216));
+        assert!(from_slice::<Vec<i64>>(b"*16777216\r\n").unwrap_err().is_incomplete());
+
+        let mut stream = StreamDeserializer::<_, String>::from_slice(b"+OK\r\n$6\r\nfoobar\r\n")
+            .with_options(DeserializerOptions::new().max_bulk_len(3));
+        assert_eq!(stream.next().unwrap().unwrap(), "OK");
+        assert!(stream.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_bytes() {
+        assert_eq!(from_slice::<ByteBuf>(b"$4\r\n\x00\xff\r\n\r\n").unwrap(), ByteBuf::from(vec![0, 255, 13, 10]));
+        assert_eq!(from_slice::<&Bytes>(b"$2\r\n\x00\xff\r\n").unwrap(), Bytes::new(b"\x00\xff"));
+        assert_eq!(from_slice::<ByteBuf>(b"+OK\r\n").unwrap(), ByteBuf::from(b"OK".to_vec()));
+
+        let b = ByteBuf::from(vec![0, 1, 2, 255]);
+        assert_eq!(from_slice::<ByteBuf>(&to_vec(&b).unwrap()).unwrap(), b);
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Tuple<'a>(
+            &'a str,
+            #[serde(with = "serde_bytes")]
+            &'a [u8]
+        );
+        let v = to_vec(&Tuple("mykey", b"\x00\xff")).unwrap();
+        assert_eq!(from_slice::<Tuple>(&v).unwrap(), Tuple("mykey", b"\x00\xff"));
+    }
+
+    #[test]
+    fn test_deserialize_simple_string_wrapper() {
+        assert_eq!(from_str::<SimpleString<String>>("+OK\r\n").unwrap(), SimpleString("OK".to_owned()));
+        assert_eq!(from_str::<SimpleString<&str>>(&to_string(&SimpleString("PONG")).unwrap()).unwrap(), SimpleString("PONG"));
+    }
+
+    #[test]
+    fn test_round_trip_map() {
+        let mut m = BTreeMap::new();
+        round_trip(m.clone());
+        m.insert("foo".to_owned(), vec![1, 2]);
+        m.insert("bar".to_owned(), vec![]);
+        round_trip(m);
+
+        let mut m = HashMap::new();
+        m.insert(10, true);
+        m.insert(-1, false);
+        round_trip(m);
+    }
+
+    #[test]
+    fn test_round_trip_struct() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        #[serde(rename_all = "camelCase")]
+        struct User {
+            first_name: String,
+            #[serde(rename = "y")]
+            year_of_birth: u16,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            email: Option<String>,
+            tags: Vec<String>,
+        }
+
+        round_trip(User { first_name: "foo".to_owned(), year_of_birth: 1990, email: None, tags: vec![] });
+        round_trip(User {
+            first_name: "foo".to_owned(),
+            year_of_birth: 1990,
+            email: Some("a@b".to_owned()),
+            tags: vec!["a".to_owned(), "b".to_owned()],
+        });
+    }
+
+    #[test]
+    fn test_deserialize_enum() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum E {
+            Unit,
+            Newtype(u32),
+            Tuple(u32, String),
+            Struct { a: u32 },
+        }
+
+        assert_eq!(from_str::<E>("$4\r\nUnit\r\n").unwrap(), E::Unit);
+        assert_eq!(from_str::<E>("+Unit\r\n").unwrap(), E::Unit);
+        assert_eq!(from_str::<E>("*2\r\n$4\r\nUnit\r\n$-1\r\n").unwrap(), E::Unit);
+        assert_eq!(from_str::<E>("*2\r\n$7\r\nNewtype\r\n:1\r\n").unwrap(), E::Newtype(1));
+        assert_eq!(from_str::<E>("*2\r\n$5\r\nTuple\r\n*2\r\n$1\r\n1\r\n$3\r\nfoo\r\n").unwrap(), E::Tuple(1, "foo".to_owned()));
+        assert_eq!(from_str::<E>("*2\r\n$6\r\nStruct\r\n*2\r\n$1\r\na\r\n$1\r\n1\r\n").unwrap(), E::Struct { a: 1 });
+
+        assert!(from_str::<E>("$5\r\nOther\r\n").is_err());
+        assert!(from_str::<E>("$7\r\nNewtype\r\n").is_err());
+        assert!(from_str::<E>("*1\r\n$7\r\nNewtype\r\n").is_err());
+        assert!(from_str::<E>("*3\r\n$7\r\nNewtype\r\n:1\r\n:2\r\n").is_err());
+
+        round_trip(E::Unit);
+        round_trip(E::Newtype(1));
+        round_trip(E::Tuple(1, "foo".to_owned()));
+        round_trip(E::Struct { a: 1 });
+        round_trip(vec![E::Unit, E::Newtype(1)]);
+
+        #[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug)]
+        #[serde(rename_all = "lowercase")]
+        enum Status {
+            Active,
+            Disabled,
+        }
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct User {
+            name: String,
+            status: Status,
+        }
+        round_trip(User { name: "foo".to_owned(), status: Status::Disabled });
+
+        let mut m = BTreeMap::new();
+        m.insert(Status::Active, 1);
+        m.insert(Status::Disabled, 2);
+        round_trip(m);
+    }
+
+    #[test]
+    fn test_deserialize_newtype_struct() {
+        #[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug)]
+        struct UserId(u64);
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Email(String);
+
+        assert_eq!(from_str::<UserId>("$3\r\n100\r\n").unwrap(), UserId(100));
+        assert_eq!(from_str::<UserId>(":100\r\n").unwrap(), UserId(100));
+        assert_eq!(from_str::<Email>("+a@b\r\n").unwrap(), Email("a@b".to_owned()));
+
+        round_trip(UserId(100));
+        round_trip(Email("a@b".to_owned()));
+        round_trip(vec![UserId(1), UserId(2)]);
+        round_trip(Some(UserId(1)));
+
+        let mut m = BTreeMap::new();
+        m.insert(UserId(1), Email("a@b".to_owned()));
+        round_trip(m);
+    }
+
+    #[test]
+    fn test_deserialize_unit_struct() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Unit;
+
+        assert_eq!(from_str::<Unit>("$-1\r\n").unwrap(), Unit);
+        assert_eq!(from_str::<Unit>("*-1\r\n").unwrap(), Unit);
+        assert!(from_str::<Unit>("+OK\r\n").is_err());
+        round_trip(Unit);
+    }
+
+    #[test]
+    fn test_deserialize_unknown_length() {
+        struct Unsized(Vec<u32>);
+
+        impl Serialize for Unsized {
+            fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().filter(|_| true))
+            }
+        }
+
+        let output = to_vec(&Unsized(vec![1, 2, 3])).unwrap();
+        assert_eq!(from_slice::<Vec<u32>>(&output).unwrap(), vec![1, 2, 3]);
+        let output = to_vec(&(Unsized(vec![]), "foo")).unwrap();
+        assert_eq!(from_slice::<(Vec<u32>, String)>(&output).unwrap(), (vec![], "foo".to_owned()));
+    }
+
+    #[test]
+    fn test_deserialize_null_encoding() {
+        use ser::{NullEncoding, Serializer, SerializerOptions};
+
+        assert_eq!(from_str::<Option<String>>("$-1\r\n").unwrap(), None);
+        assert_eq!(from_str::<Option<String>>("*-1\r\n").unwrap(), None);
+        assert_eq!(from_str::<Option<Vec<String>>>("*-1\r\n").unwrap(), None);
+        assert_eq!(from_str::<()>("*-1\r\n").unwrap(), ());
+
+        let value = vec![None, Some("foo".to_owned())];
+        let mut ser = Serializer::new(Vec::new()).with_options(SerializerOptions::new().null_encoding(NullEncoding::Array));
+        value.serialize(&mut ser).unwrap();
+        assert_eq!(from_slice::<Vec<Option<String>>>(&ser.into_inner()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_round_trip_display() {
+        use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+        round_trip(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+        round_trip(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        round_trip("10.0.0.1:6379".parse::<SocketAddr>().unwrap());
+        assert_eq!(from_str::<Ipv4Addr>("+127.0.0.1\r\n").unwrap(), Ipv4Addr::new(127, 0, 0, 1));
+    }
+
+    #[test]
+    fn test_deserialize_bool_encoding() {
+        use ser::{BoolEncoding, IntegerEncoding, Serializer, SerializerOptions};
+
+        for input in &["$4\r\ntrue\r\n", "+true\r\n", "$1\r\n1\r\n", ":1\r\n", "#t\r\n"] {
+            assert!(from_str::<bool>(input).unwrap());
+        }
+        for input in &["$5\r\nfalse\r\n", "+false\r\n", "$1\r\n0\r\n", ":0\r\n", "#f\r\n"] {
+            assert!(!from_str::<bool>(input).unwrap());
+        }
+        assert!(from_str::<bool>("#x\r\n").is_err());
+        assert!(from_str::<bool>("$1\r\n2\r\n").is_err());
+        assert!(from_str::<u8>("#t\r\n").is_err());
+        assert_eq!(from_str::<Vec<bool>>("*2\r\n#t\r\n#f\r\n").unwrap(), vec![true, false]);
+        assert!(from_reader::<_, bool>(BufReader::new(&b"#t\r\n"[..])).unwrap());
+
+        let options = [
+            SerializerOptions::new().bool_encoding(BoolEncoding::Text),
+            SerializerOptions::new().bool_encoding(BoolEncoding::Integer),
+            SerializerOptions::new().bool_encoding(BoolEncoding::Integer).integer_encoding(IntegerEncoding::Native),
+            SerializerOptions::new().bool_encoding(BoolEncoding::Resp3),
+        ];
+        let mut value = BTreeMap::new();
+        value.insert(true, vec![true, false]);
+        value.insert(false, vec![]);
+        for options in &options {
+            let mut ser = Serializer::new(Vec::new()).with_options(*options);
+            value.serialize(&mut ser).unwrap();
+            assert_eq!(from_slice::<BTreeMap<bool, Vec<bool>>>(&ser.into_inner()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_float_forms() {
+        use ser::{FloatEncoding, Serializer, SerializerOptions};
+        use std::{f32, f64};
+
+        assert_eq!(from_str::<f64>("$4\r\n+inf\r\n").unwrap(), f64::INFINITY);
+        assert_eq!(from_str::<f64>("$3\r\ninf\r\n").unwrap(), f64::INFINITY);
+        assert_eq!(from_str::<f64>("$4\r\n-inf\r\n").unwrap(), f64::NEG_INFINITY);
+        assert_eq!(from_str::<f32>("$4\r\n-inf\r\n").unwrap(), f32::NEG_INFINITY);
+        assert_eq!(from_str::<f64>("$4\r\n1e-5\r\n").unwrap(), 1e-5);
+        assert_eq!(from_str::<f64>("$18\r\n3.0000000000000004\r\n").unwrap(), 3.0000000000000004);
+
+        assert_eq!(from_str::<f64>(",1.34\r\n").unwrap(), 1.34);
+        assert_eq!(from_str::<f32>(",1.34\r\n").unwrap(), 1.34);
+        assert_eq!(from_str::<f64>(",-inf\r\n").unwrap(), f64::NEG_INFINITY);
+        assert!(from_str::<f64>(",nan\r\n").unwrap().is_nan());
+        assert!(from_str::<f64>(",x\r\n").is_err());
+        assert_eq!(from_str::<String>(",1.5\r\n").unwrap(), "1.5");
+        assert_eq!(from_str::<&str>(",-inf\r\n").unwrap(), "-inf");
+        assert_eq!(from_str::<Vec<String>>("*2\r\n+a\r\n,1.5\r\n").unwrap(), vec!["a", "1.5"]);
+        assert_eq!(from_str::<&Bytes>(",1.5\r\n").unwrap(), Bytes::new(b"1.5"));
+        assert_eq!(from_str::<Vec<f64>>("*2\r\n,1\r\n,1e300\r\n").unwrap(), vec![1.0, 1e300]);
+        assert_eq!(from_reader::<_, f64>(BufReader::new(&b",1.5\r\n"[..])).unwrap(), 1.5);
+
+        let value = vec![0.0, -1.34, 0.1, 1e-5, 1.5e300, f64::MIN_POSITIVE, f64::MAX, f64::INFINITY, f64::NEG_INFINITY];
+        for encoding in &[FloatEncoding::Text, FloatEncoding::Redis, FloatEncoding::Resp3] {
+            let mut ser = Serializer::new(Vec::new()).with_options(SerializerOptions::new().float_encoding(*encoding));
+            value.serialize(&mut ser).unwrap();
+            assert_eq!(from_slice::<Vec<f64>>(&ser.into_inner()).unwrap(), value);
+
+            let value = vec![0.1_f32, f32::MAX, f32::MIN_POSITIVE];
+            let mut ser = Serializer::new(Vec::new()).with_options(SerializerOptions::new().float_encoding(*encoding));
+            value.serialize(&mut ser).unwrap();
+            assert_eq!(from_slice::<Vec<f32>>(&ser.into_inner()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_big_number() {
+        use ser::{BigNumberEncoding, IntegerEncoding, Serializer, SerializerOptions};
+
+        assert_eq!(from_str::<i128>("(-170141183460469231731687303715884105728\r\n").unwrap(), i128::MIN);
+        assert_eq!(from_str::<u128>("(340282366920938463463374607431768211455\r\n").unwrap(), u128::MAX);
+        assert_eq!(from_str::<u128>("$39\r\n340282366920938463463374607431768211455\r\n").unwrap(), u128::MAX);
+        assert_eq!(from_str::<i128>(":-100\r\n").unwrap(), -100);
+        assert_eq!(from_str::<u64>("(18446744073709551615\r\n").unwrap(), u64::MAX);
+        assert!(from_str::<u64>("(18446744073709551616\r\n").is_err());
+        assert!(from_str::<u128>("(-1\r\n").is_err());
+        assert!(from_str::<u128>("(12x\r\n").is_err());
+
+        // Values that do not fit in any integer type fall back to strings.
+        let huge = "3492890328409238509324850943850943825024385";
+        assert_eq!(from_str::<String>(&format!("({}\r\n", huge)).unwrap(), huge);
+        assert_eq!(from_str::<&str>(&format!("({}\r\n", huge)).unwrap(), huge);
+        assert_eq!(from_str::<ByteBuf>("(1\r\n").unwrap(), ByteBuf::from(b"1".to_vec()));
+
+        // deserialize_any visits the smallest type that fits.
+        struct Kind;
+        impl<'de> Visitor<'de> for Kind {
+            type Value = String;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a number")
+            }
+            fn visit_i128<E>(self, v: i128) -> ::std::result::Result<String, E> {
+                Ok(format!("i128 {}", v))
+            }
+            fn visit_u128<E>(self, v: u128) -> ::std::result::Result<String, E> {
+                Ok(format!("u128 {}", v))
+            }
+            fn visit_str<E>(self, v: &str) -> ::std::result::Result<String, E> {
+                Ok(format!("str {}", v))
+            }
+        }
+        let any = |input: &str| de::Deserializer::deserialize_any(&mut Deserializer::from_str(input), Kind).unwrap();
+        assert_eq!(any("(-1\r\n"), "i128 -1");
+        assert_eq!(any("(340282366920938463463374607431768211455\r\n"), "u128 340282366920938463463374607431768211455");
+        assert_eq!(any(&format!("({}\r\n", huge)), format!("str {}", huge));
+
+        assert_eq!(from_reader::<_, u128>(BufReader::new(&b"(1\r\n"[..])).unwrap(), 1);
+
+        round_trip(vec![i128::MIN, -1, 0, i128::MAX]);
+        round_trip(vec![0, u64::MAX as u128, u128::MAX]);
+        let options = [
+            SerializerOptions::new().integer_encoding(IntegerEncoding::Native),
+            SerializerOptions::new().big_number_encoding(BigNumberEncoding::Resp3),
+            SerializerOptions::new().big_number_encoding(BigNumberEncoding::Resp3).integer_encoding(IntegerEncoding::Native),
+        ];
+        for options in &options {
+            let value = (vec![i128::MIN, -1, i128::MAX], vec![1, u128::MAX], u64::MAX);
+            let mut ser = Serializer::new(Vec::new()).with_options(*options);
+            value.serialize(&mut ser).unwrap();
+            assert_eq!(from_slice::<(Vec<i128>, Vec<u128>, u64)>(&ser.into_inner()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_resp3() {
+        use std::collections::BTreeSet;
+
+        let mut m = BTreeMap::new();
+        m.insert("a".to_owned(), 1);
+        m.insert("b".to_owned(), 2);
+        assert_eq!(from_str::<BTreeMap<String, u8>>("%2\r\n+a\r\n:1\r\n+b\r\n:2\r\n").unwrap(), m);
+        assert_eq!(from_str::<BTreeMap<String, u8>>("%0\r\n").unwrap(), BTreeMap::new());
+        assert!(from_str::<BTreeMap<String, u8>>("%1\r\n+a\r\n").unwrap_err().is_incomplete());
+        let fields = vec!["a", "1", "b", "2"];
+        assert_eq!(from_str::<Vec<String>>("*4\r\n+a\r\n+1\r\n+b\r\n+2\r\n").unwrap(), fields);
+        assert_eq!(from_str::<Vec<String>>("%2\r\n+a\r\n+1\r\n+b\r\n+2\r\n").unwrap(), fields);
+        assert_eq!(from_str::<Vec<String>>("%?\r\n+a\r\n+1\r\n+b\r\n+2\r\n.\r\n").unwrap(), fields);
+
+        let set: BTreeSet<_> = vec!["a".to_owned(), "b".to_owned()].into_iter().collect();
+        assert_eq!(from_str::<BTreeSet<String>>("~2\r\n+a\r\n+b\r\n").unwrap(), set);
+        assert_eq!(from_str::<Vec<String>>(">2\r\n+message\r\n+hello\r\n").unwrap(), vec!["message", "hello"]);
+
+        assert_eq!(from_str::<Option<String>>("_\r\n").unwrap(), None);
+        assert_eq!(from_str::<()>("_\r\n").unwrap(), ());
+        assert!(from_str::<()>("_x\r\n").is_err());
+
+        assert_eq!(from_str::<String>("=15\r\ntxt:Some string\r\n").unwrap(), "Some string");
+        assert_eq!(from_str::<&str>("=4\r\nmkd:\r\n").unwrap(), "");
+        assert_eq!(from_str::<u8>("=5\r\ntxt:1\r\n").unwrap(), 1);
+        assert!(from_str::<String>("=3\r\ntxt\r\n").is_err());
+
+        match from_str::<String>("!21\r\nSYNTAX invalid syntax\r\n").unwrap_err().kind() {
+            ErrorKind::ErrorReply(e) => assert_eq!(e, &RedisError::new("SYNTAX", "invalid syntax")),
+            kind => panic!("unexpected error: {}", kind),
+        }
+        assert_eq!(from_str::<Reply<String>>("!21\r\nSYNTAX invalid syntax\r\n").unwrap().into_result(),
+                   Err(RedisError::new("SYNTAX", "invalid syntax")));
+        assert_eq!(from_str::<Reply<String>>("|1\r\n+a\r\n+b\r\n-ERR x\r\n").unwrap().into_result(),
+                   Err(RedisError::new("ERR", "x")));
+        assert_eq!(from_str::<Reply<String>>("|1\r\n+a\r\n+b\r\n+OK\r\n").unwrap().into_result(), Ok("OK".to_owned()));
+        assert_eq!(from_reader::<_, Reply<String>>(&b"|1\r\n+a\r\n+b\r\n!5\r\nERR x\r\n"[..]).unwrap().into_result(),
+                   Err(RedisError::new("ERR", "x")));
+
+        // Attributes are skipped.
+        let input = "|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.1923\r\n*2\r\n:2039123\r\n:9543892\r\n";
+        assert_eq!(from_str::<Vec<u32>>(input).unwrap(), vec![2039123, 9543892]);
+        assert_eq!(from_str::<Vec<u8>>("*2\r\n|1\r\n+a\r\n+b\r\n:1\r\n:2\r\n").unwrap(), vec![1, 2]);
+        let input = "|0\r\n".repeat(10000) + ":1\r\n";
+        assert_eq!(from_str::<u8>(&input).unwrap(), 1);
+        assert_eq!(from_str::<Vec<u8>>(&format!("*1\r\n{}", input)).unwrap(), vec![1]);
+        assert_eq!(from_reader::<_, u8>(input.as_bytes()).unwrap(), 1);
+
+        // Streamed aggregates.
+        assert_eq!(from_str::<Vec<u8>>("*?\r\n:1\r\n:2\r\n.\r\n").unwrap(), vec![1, 2]);
+        assert_eq!(from_str::<(u8, u8)>("*?\r\n:1\r\n:2\r\n.\r\n").unwrap(), (1, 2));
+        assert_eq!(from_str::<Vec<u8>>("~?\r\n.\r\n").unwrap(), vec![]);
+        assert_eq!(from_str::<BTreeMap<String, u8>>("%?\r\n+a\r\n:1\r\n+b\r\n:2\r\n.\r\n").unwrap(), m);
+        assert_eq!(from_str::<BTreeMap<String, u8>>("*?\r\n+a\r\n:1\r\n+b\r\n:2\r\n.\r\n").unwrap(), m);
+        // The visitor stopping early is reported as trailing elements.
+        fn trailing<'a, T: Deserialize<'a> + Debug>(input: &'a str) -> String {
+            from_str::<T>(input).unwrap_err().to_string()
+        }
+        assert_eq!(trailing::<(u8, u8)>("*3\r\n:1\r\n:2\r\n:3\r\n"), "deserialization error: 'trailing elements in array'");
+        assert_eq!(trailing::<(u8, u8)>("*?\r\n:1\r\n:2\r\n:3\r\n.\r\n"),
+                   "deserialization error: 'trailing elements in streamed array'");
+
+        // Reads the first entry of a map only.
+        #[derive(Debug)]
+        struct FirstEntry;
+        impl<'de> Deserialize<'de> for FirstEntry {
+            fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+                struct FirstEntryVisitor;
+                impl<'de> Visitor<'de> for FirstEntryVisitor {
+                    type Value = FirstEntry;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                        formatter.write_str("a map")
+                    }
+
+                    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> ::std::result::Result<FirstEntry, A::Error> {
+                        map.next_entry::<String, u8>()?;
+                        Ok(FirstEntry)
+                    }
+                }
+                deserializer.deserialize_map(FirstEntryVisitor)
+            }
+        }
+        assert!(from_str::<FirstEntry>("%1\r\n+a\r\n:1\r\n").is_ok());
+        assert_eq!(trailing::<FirstEntry>("%2\r\n+a\r\n:1\r\n+b\r\n:2\r\n"), "deserialization error: 'trailing entries in map'");
+        assert_eq!(trailing::<FirstEntry>("%?\r\n+a\r\n:1\r\n+b\r\n:2\r\n.\r\n"),
+                   "deserialization error: 'trailing entries in streamed map'");
+        assert!(from_str::<Vec<u8>>("*?\r\n:1\r\n").unwrap_err().is_incomplete());
+        assert!(from_str::<Vec<u8>>("*?\r\n.x\r\n").is_err());
+
+        // Everything ignored is skipped whole.
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct User {
+            name: String,
+        }
+        let input = "%3\r\n+skip\r\n%?\r\n+a\r\n~1\r\n_\r\n.\r\n+name\r\n=7\r\ntxt:foo\r\n+flag\r\n#t\r\n";
+        assert_eq!(from_str::<User>(input).unwrap(), User { name: "foo".to_owned() });
+
+        // The same frames are understood by the reader.
+        let input = "|1\r\n+a\r\n+b\r\n:1\r\n%?\r\n+a\r\n~1\r\n_\r\n.\r\n=5\r\ntxt:x\r\n!3\r\nERR\r\n>1\r\n(1\r\n*?\r\n.\r\n";
+        let stream = StreamDeserializer::<_, ::serde::de::IgnoredAny>::from_reader(input.as_bytes());
+        assert_eq!(stream.filter(|value| value.is_ok()).count(), 6);
+        assert_eq!(from_reader::<_, BTreeMap<String, u8>>(BufReader::new(&b"%?\r\n+a\r\n:1\r\n+b\r\n:2\r\n.\r\n"[..])).unwrap(), m);
+        assert!(from_reader::<_, Vec<u8>>(BufReader::new(&b"*?\r\n:1\r\n"[..])).unwrap_err().is_incomplete());
+        assert!(from_reader::<_, Vec<u8>>(BufReader::new(&b".\r\n"[..])).is_err());
+
+        // Limits apply to RESP3 aggregates too.
+        for input in &[&b"%1\r\n+a\r\n*1\r\n:1\r\n"[..], &b"*?\r\n~?\r\n.\r\n.\r\n"[..]] {
+            let options = DeserializerOptions::new().max_depth(1);
+            let err = ::serde::de::IgnoredAny::deserialize(&mut Deserializer::from_slice(input).with_options(options));
+            assert!(matches!(*err.unwrap_err().kind(), ErrorKind::DepthLimitExceeded(1)));
+            let err = from_reader_with_options::<_, ::serde::de::IgnoredAny>(*input, options).unwrap_err();
+            assert!(matches!(*err.kind(), ErrorKind::DepthLimitExceeded(1)));
+        }
+        let options = DeserializerOptions::new().max_aggregate_len(1);
+        let err = from_reader_with_options::<_, ::serde::de::IgnoredAny>(&b"%2\r\n"[..], options).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::AggregateLengthLimitExceeded(1)));
+
+        // Streamed aggregates count their elements, or the entries of a map, as they are read.
+        fn streamed<T: DeserializeOwned>(input: &[u8]) -> (Result<T>, Result<T>) {
+            let options = DeserializerOptions::new().max_aggregate_len(2);
+            let from_slice = T::deserialize(&mut Deserializer::from_slice(input).with_options(options));
+            (from_slice, from_reader_with_options(input, options))
+        }
+        fn assert_exceeded<T>((from_slice, from_reader): (Result<T>, Result<T>)) {
+            assert!(matches!(*from_slice.err().unwrap().kind(), ErrorKind::AggregateLengthLimitExceeded(2)));
+            assert!(matches!(*from_reader.err().unwrap().kind(), ErrorKind::AggregateLengthLimitExceeded(2)));
+        }
+        let (a, b) = streamed::<Vec<u8>>(b"*?\r\n:1\r\n:2\r\n.\r\n");
+        assert_eq!((a.unwrap(), b.unwrap()), (vec![1, 2], vec![1, 2]));
+        let (a, b) = streamed::<BTreeMap<String, u8>>(b"%?\r\n+a\r\n:1\r\n+b\r\n:2\r\n.\r\n");
+        assert_eq!((a.unwrap(), b.unwrap()), (m.clone(), m.clone()));
+        assert_exceeded(streamed::<Vec<u8>>(b"*?\r\n:1\r\n:2\r\n:3\r\n:4\r\n.\r\n"));
+        assert_exceeded(streamed::<Vec<u8>>(b"~?\r\n:1\r\n:2\r\n:3\r\n"));
+        assert_exceeded(streamed::<BTreeMap<String, u8>>(b"%?\r\n+a\r\n:1\r\n+b\r\n:2\r\n+c\r\n:3\r\n.\r\n"));
+        assert_exceeded(streamed::<::serde::de::IgnoredAny>(b"*?\r\n:1\r\n:2\r\n:3\r\n.\r\n"));
+        assert_exceeded(streamed::<::serde::de::IgnoredAny>(b"%?\r\n+a\r\n:1\r\n+b\r\n:2\r\n+c\r\n"));
+        let (a, b) = streamed::<::serde::de::IgnoredAny>(b"%?\r\n+a\r\n:1\r\n|1\r\n+x\r\n+y\r\n+b\r\n:2\r\n.\r\n");
+        assert!(a.is_ok() && b.is_ok());
+        let mut stream = StreamDeserializer::<_, Vec<u8>>::from_reader(&b"*?\r\n:1\r\n:2\r\n:3\r\n"[..])
+            .with_options(DeserializerOptions::new().max_aggregate_len(2));
+        assert!(matches!(*stream.next().unwrap().unwrap_err().kind(), ErrorKind::AggregateLengthLimitExceeded(2)));
+    }
+
+    #[test]
+    fn test_round_trip_resp3() {
+        use ser::{Protocol, Serializer, SerializerOptions, Set, UnknownLength, Verbatim};
+        use std::collections::BTreeSet;
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Reply {
+            name: String,
+            admin: bool,
+            score: f64,
+            id: u128,
+            email: Option<String>,
+            tags: Set<BTreeSet<String>>,
+            counts: BTreeMap<String, i64>,
+            point: Point,
+        }
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum Point {
+            Xy { x: i8, y: i8 },
+        }
+
+        let mut counts = BTreeMap::new();
+        counts.insert("a".to_owned(), -1);
+        let value = Reply {
+            name: "foo".to_owned(),
+            admin: true,
+            score: 1e-5,
+            id: u128::MAX,
+            email: None,
+            tags: Set(vec!["x".to_owned(), "y".to_owned()].into_iter().collect()),
+            counts,
+            point: Point::Xy { x: 1, y: -1 },
+        };
+        for protocol in &[Protocol::Resp2, Protocol::Resp3] {
+            for unknown_length in &[UnknownLength::Buffer, UnknownLength::Stream] {
+                let options = SerializerOptions::new().protocol(*protocol).unknown_length(*unknown_length);
+                let mut ser = Serializer::new(Vec::new()).with_options(options);
+                value.serialize(&mut ser).unwrap();
+                let output = ser.into_inner();
+                assert_eq!(from_slice::<Reply>(&output).unwrap(), value);
+                assert_eq!(from_reader::<_, Reply>(&output[..]).unwrap(), value);
+            }
+        }
+
+        let mut ser = Serializer::new(Vec::new()).with_options(SerializerOptions::new().protocol(Protocol::Resp3));
+        Verbatim::markdown("# title").serialize(&mut ser).unwrap();
+        assert_eq!(from_slice::<String>(&ser.into_inner()).unwrap(), "# title");
+    }
+
+    #[test]
+    fn test_deserialize_from_reader_empty_line() {
+        fn is_de_error<T>(result: Result<T>) -> bool {
+            matches!(result.map(|_| ()).unwrap_err().kind(), ErrorKind::DeError(_))
+        }
+
+        assert!(is_de_error(from_reader::<_, String>(&b"\r\n"[..])));
+        assert!(is_de_error(from_reader::<_, String>(&b"\r\n%:"[..])));
+        assert!(is_de_error(from_reader_with_options::<_, String>(&b"\r\n"[..], DeserializerOptions::new())));
+
+        let mut stream = StreamDeserializer::<_, String>::from_reader(&b"+OK\r\n\r\n"[..]);
+        assert_eq!(stream.next().unwrap().unwrap(), "OK");
+        assert!(is_de_error(stream.next().unwrap()));
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_deserialize_invalid_vs_incomplete() {
+        fn classify<T>(result: Result<T>) -> &'static str {
+            match result.map(|_| ()) {
+                Ok(()) => "ok",
+                Err(ref e) if e.is_incomplete() => "incomplete",
+                Err(_) => "invalid",
+            }
+        }
+
+        let cases: &[(&[u8], &str)] = &[
+            (b"GARBAGE", "invalid"),
+            (b"?t,_t", "invalid"),
+            (b"+\r>", "invalid"),
+            (b"+OK\rx\r\n", "invalid"),
+            (b"\r\n", "invalid"),
+            (b"*2\r\nGARBAGE", "invalid"),
+            (b"", "incomplete"),
+            (b"+OK", "incomplete"),
+            (b"+OK\r", "incomplete"),
+            (b"*2\r\n+a\r\n", "incomplete"),
+        ];
+        for &(input, expected) in cases {
+            let slice = classify(from_slice::<::serde::de::IgnoredAny>(input));
+            let reader = classify(from_reader::<_, ::serde::de::IgnoredAny>(input));
+            assert_eq!((slice, reader), (expected, expected), "input {:?}", String::from_utf8_lossy(input));
+        }
+    }
+}
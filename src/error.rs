@@ -1,8 +1,16 @@
+// error_chain 0.11 calls the deprecated `description` and `cause` of foreign errors.
+#![allow(deprecated)]
+
 use serde::de::Error as DeError;
 use serde::ser::Error as SerError;
-use std::fmt::Display;
+use std::error;
+use std::fmt::{self, Display};
 
 error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
     errors {
         UnsupportedOperation(t: String) {
             description("unsupported operation")
@@ -18,9 +26,91 @@ error_chain! {
             description("serialization error")
             display("serialization error: '{}'", t)
         }
+
+        // The peer answered with an error reply. Unlike DeError the input itself is valid.
+        ErrorReply(e: RedisError) {
+            description("error reply")
+            display("error reply: '{}'", e)
+        }
+
+        DepthLimitExceeded(limit: usize) {
+            description("nesting depth limit exceeded")
+            display("nesting depth limit exceeded: '{}'", limit)
+        }
+
+        BulkLengthLimitExceeded(limit: usize) {
+            description("bulk string length limit exceeded")
+            display("bulk string length limit exceeded: '{}'", limit)
+        }
+
+        AggregateLengthLimitExceeded(limit: usize) {
+            description("aggregate length limit exceeded")
+            display("aggregate length limit exceeded: '{}'", limit)
+        }
+
+        InlineLengthLimitExceeded(limit: usize) {
+            description("inline length limit exceeded")
+            display("inline length limit exceeded: '{}'", limit)
+        }
+
+        TotalBytesLimitExceeded(limit: usize) {
+            description("total bytes limit exceeded")
+            display("total bytes limit exceeded: '{}'", limit)
+        }
+
+        // The input ends in the middle of a frame. Carries the minimum number of further bytes
+        // needed when it is known.
+        Incomplete(needed: Option<usize>) {
+            description("incomplete frame")
+            display("incomplete frame: {}", match *needed {
+                Some(n) => format!("at least {} more bytes needed", n),
+                None => "more bytes needed".to_owned(),
+            })
+        }
     }
 }
 
+impl Error {
+    // Whether the error only means that more input is needed, as opposed to invalid input.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(*self.kind(), ErrorKind::Incomplete(_))
+    }
+}
+
+// An error reply such as "-WRONGTYPE Operation against a key holding the wrong kind of value".
+// The code is the first word of the reply and the message is the rest of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisError {
+    pub code: String,
+    pub message: String,
+}
+
+impl RedisError {
+    pub fn new<C: Into<String>, M: Into<String>>(code: C, message: M) -> Self {
+        RedisError { code: code.into(), message: message.into() }
+    }
+
+    // Split the text of an error reply, without the leading '-', into code and message.
+    pub fn parse(reply: &str) -> Self {
+        match reply.find(' ') {
+            Some(i) => RedisError::new(&reply[..i], &reply[i + 1..]),
+            None => RedisError::new(reply, ""),
+        }
+    }
+}
+
+impl Display for RedisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{} {}", self.code, self.message)
+        }
+    }
+}
+
+impl error::Error for RedisError {}
+
 impl DeError for Error {
     fn custom<T: Display>(msg: T) -> Self {
         ErrorKind::DeError(msg.to_string()).into()
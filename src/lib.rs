@@ -2,6 +2,8 @@ extern crate serde;
 #[cfg(test)]
 #[macro_use]
 extern crate serde_derive;
+#[cfg(test)]
+extern crate serde_bytes;
 #[macro_use]
 extern crate error_chain;
 extern crate num_traits;
@@ -10,7 +12,10 @@ mod error;
 pub mod ser;
 pub mod de;
 
-pub use error::{Error, ErrorKind, Result};
-pub use ser::{to_string, Serializer};
+pub use error::{Error, ErrorKind, RedisError, Result};
+pub use ser::{to_string, to_vec, to_writer, BigNumberEncoding, BoolEncoding, FloatEncoding, IntegerEncoding, NanPolicy,
+              NullEncoding, Protocol, Serializer, SerializerOptions, Set, SimpleString, UnknownLength, Verbatim};
 pub use serde::ser::Serialize;
-//pub use de::{from_str, Deserializer};
\ No newline at end of file
+pub use de::{from_reader, from_reader_with_options, from_slice, from_str, Deserializer, DeserializerOptions, Reply,
+             StreamDeserializer};
+pub use serde::de::Deserialize;
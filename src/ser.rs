@@ -1,129 +1,458 @@
+use serde::de::{Deserialize, Deserializer};
 use serde::ser::{self, Serialize, Impossible};
 use error::{Error, ErrorKind, Result};
-use std::fmt::Display;
-use num_traits::Num;
+use std::fmt::{self, Display};
+use std::io;
+use num_traits::{Float, Num};
+
+// How integers are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerEncoding {
+    // Bulk strings such as "$3\r\n100\r\n", as expected in command arguments.
+    Bulk,
+    // RESP integers such as ":100\r\n", as expected in replies to INCR or LLEN. Values that do
+    // not fit in i64 are written as bulk strings.
+    Native,
+}
+
+// How integers that do not fit in a RESP integer are encoded, that is u64 values above
+// i64::MAX and most i128 and u128 values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BigNumberEncoding {
+    // Bulk strings such as "$20\r\n18446744073709551616\r\n".
+    Bulk,
+    // RESP3 big numbers such as "(18446744073709551616\r\n".
+    Resp3,
+}
+
+// How booleans are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoolEncoding {
+    // Bulk strings "$4\r\ntrue\r\n" and "$5\r\nfalse\r\n".
+    Text,
+    // Integers 1 and 0, as used by Redis for flags. They follow the IntegerEncoding, so true is
+    // encoded into "$1\r\n1\r\n" or ":1\r\n".
+    Integer,
+    // RESP3 booleans "#t\r\n" and "#f\r\n".
+    Resp3,
+}
+
+// How floats are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatEncoding {
+    // Bulk strings of the Rust formatting, for example "$4\r\n1.34\r\n". Infinity is written
+    // as "inf" and NaN as "NaN".
+    Text,
+    // Bulk strings Redis parses as doubles, as expected by ZADD or INCRBYFLOAT. Values use the
+    // shortest representation that round-trips, with an exponent when very small or large such
+    // as "1e-5", and infinity is written as "inf" or "-inf". NaN follows the NanPolicy.
+    Redis,
+    // RESP3 doubles such as ",1.34\r\n" or ",-inf\r\n", formatted like FloatEncoding::Redis.
+    // NaN follows the NanPolicy.
+    Resp3,
+}
+
+// How NaN is handled by FloatEncoding::Redis and FloatEncoding::Resp3, as Redis rejects it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NanPolicy {
+    // Fail with ErrorKind::SerError.
+    Error,
+    // Write null instead, following the NullEncoding.
+    Null,
+}
+
+// How None, unit and unit structs are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullEncoding {
+    // The null bulk string "$-1\r\n", as in replies to GET on a missing key.
+    Bulk,
+    // The null array "*-1\r\n", as in replies to BLPOP on timeout.
+    Array,
+    // The RESP3 null "_\r\n".
+    Resp3,
+}
+
+// How sequences and maps whose length is not known upfront are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownLength {
+    // Elements are buffered in memory until the end, then written after the "*<n>\r\n" header.
+    Buffer,
+    // RESP3 streamed aggregates such as "*?\r\n$3\r\nfoo\r\n.\r\n", which need no buffering but
+    // are only understood by RESP3 peers.
+    Stream,
+}
+
+// The version of the protocol spoken by the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    // RESP2, understood by every Redis version.
+    Resp2,
+    // RESP3, negotiated with "HELLO 3" since Redis 6. Maps and structs are written as RESP3 maps
+    // "%<number-of-entries>\r\n<key><value>...", Set as "~" and Verbatim as "=".
+    Resp3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerializerOptions {
+    protocol: Protocol,
+    integer_encoding: IntegerEncoding,
+    big_number_encoding: BigNumberEncoding,
+    bool_encoding: BoolEncoding,
+    float_encoding: FloatEncoding,
+    nan_policy: NanPolicy,
+    null_encoding: NullEncoding,
+    unknown_length: UnknownLength,
+    overrides: Overrides,
+}
 
-pub struct Serializer {
-    output: String,
+// The encodings set explicitly, which selecting the protocol leaves alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Overrides {
+    big_number_encoding: bool,
+    bool_encoding: bool,
+    float_encoding: bool,
+    null_encoding: bool,
 }
 
-impl Serializer {
-    fn serialize_num_types<T: Num + Display>(&self, v: T) -> String {
-        format!("${}\r\n{}\r\n", v.to_string().len(), v)
+impl SerializerOptions {
+    pub fn new() -> Self {
+        SerializerOptions {
+            protocol: Protocol::Resp2,
+            integer_encoding: IntegerEncoding::Bulk,
+            big_number_encoding: BigNumberEncoding::Bulk,
+            bool_encoding: BoolEncoding::Text,
+            float_encoding: FloatEncoding::Text,
+            nan_policy: NanPolicy::Error,
+            null_encoding: NullEncoding::Bulk,
+            unknown_length: UnknownLength::Buffer,
+            overrides: Overrides::default(),
+        }
+    }
+
+    // Select the protocol together with its encodings of null, booleans, floats and big numbers.
+    // Encodings set explicitly win, whether they are set before or after the protocol.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        let (big_number_encoding, bool_encoding, float_encoding, null_encoding) = match protocol {
+            Protocol::Resp2 => (BigNumberEncoding::Bulk, BoolEncoding::Text, FloatEncoding::Text, NullEncoding::Bulk),
+            Protocol::Resp3 => (BigNumberEncoding::Resp3, BoolEncoding::Resp3, FloatEncoding::Resp3, NullEncoding::Resp3),
+        };
+        self.protocol = protocol;
+        if !self.overrides.big_number_encoding {
+            self.big_number_encoding = big_number_encoding;
+        }
+        if !self.overrides.bool_encoding {
+            self.bool_encoding = bool_encoding;
+        }
+        if !self.overrides.float_encoding {
+            self.float_encoding = float_encoding;
+        }
+        if !self.overrides.null_encoding {
+            self.null_encoding = null_encoding;
+        }
+        self
+    }
+
+    pub fn integer_encoding(mut self, integer_encoding: IntegerEncoding) -> Self {
+        self.integer_encoding = integer_encoding;
+        self
+    }
+
+    pub fn big_number_encoding(mut self, big_number_encoding: BigNumberEncoding) -> Self {
+        self.big_number_encoding = big_number_encoding;
+        self.overrides.big_number_encoding = true;
+        self
+    }
+
+    pub fn bool_encoding(mut self, bool_encoding: BoolEncoding) -> Self {
+        self.bool_encoding = bool_encoding;
+        self.overrides.bool_encoding = true;
+        self
+    }
+
+    pub fn float_encoding(mut self, float_encoding: FloatEncoding) -> Self {
+        self.float_encoding = float_encoding;
+        self.overrides.float_encoding = true;
+        self
     }
 
-    fn serialize_null(&self) -> String {
-        format!("$-1\r\n")
+    pub fn nan_policy(mut self, nan_policy: NanPolicy) -> Self {
+        self.nan_policy = nan_policy;
+        self
+    }
+
+    pub fn null_encoding(mut self, null_encoding: NullEncoding) -> Self {
+        self.null_encoding = null_encoding;
+        self.overrides.null_encoding = true;
+        self
+    }
+
+    pub fn unknown_length(mut self, unknown_length: UnknownLength) -> Self {
+        self.unknown_length = unknown_length;
+        self
     }
 }
 
-pub fn to_string<T>(value: &T) -> Result<String> where T: Serialize {
-    let mut serializer = Serializer { output: String::new() };
-    value.serialize(&mut serializer)?;
-    Ok(serializer.output)
+impl Default for SerializerOptions {
+    fn default() -> Self {
+        SerializerOptions::new()
+    }
+}
+
+pub struct Serializer<W> {
+    writer: W,
+    options: SerializerOptions,
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl<W: io::Write> Serializer<W> {
+    pub fn new(writer: W) -> Self {
+        Serializer { writer, options: SerializerOptions::new() }
+    }
+
+    pub fn with_options(mut self, options: SerializerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // Write a RESP bulk string "$<number-of-bytes>\r\n<data>\r\n".
+    fn write_bulk(&mut self, v: &[u8]) -> Result<()> {
+        write!(self.writer, "${}\r\n", v.len())?;
+        self.writer.write_all(v)?;
+        self.writer.write_all(b"\r\n")?;
+        Ok(())
+    }
+
+    fn serialize_num_types<T: Num + Display>(&mut self, v: T) -> Result<()> {
+        self.write_bulk(v.to_string().as_bytes())
+    }
+
+    fn serialize_integer<T: Num + Display>(&mut self, v: T) -> Result<()> {
+        match self.options.integer_encoding {
+            IntegerEncoding::Bulk => self.serialize_num_types(v),
+            IntegerEncoding::Native => {
+                write!(self.writer, ":{}\r\n", v)?;
+                Ok(())
+            }
+        }
+    }
+
+    // Values within the i64 range follow the IntegerEncoding, others the BigNumberEncoding.
+    fn serialize_big_integer<T: Num + Display>(&mut self, v: T, fits_i64: bool) -> Result<()> {
+        if fits_i64 {
+            return self.serialize_integer(v);
+        }
+        match self.options.big_number_encoding {
+            BigNumberEncoding::Bulk => self.serialize_num_types(v),
+            BigNumberEncoding::Resp3 => {
+                write!(self.writer, "({}\r\n", v)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn serialize_float<T: Float + Display + fmt::LowerExp>(&mut self, v: T) -> Result<()> {
+        if self.options.float_encoding == FloatEncoding::Text {
+            return self.serialize_num_types(v);
+        }
+        if v.is_nan() {
+            return match self.options.nan_policy {
+                NanPolicy::Error => Err(ErrorKind::SerError("NaN is not a valid Redis double".to_owned()).into()),
+                NanPolicy::Null => self.serialize_null(),
+            };
+        }
+        let text = format_double(v);
+        match self.options.float_encoding {
+            FloatEncoding::Resp3 => {
+                write!(self.writer, ",{}\r\n", text)?;
+                Ok(())
+            }
+            _ => self.write_bulk(text.as_bytes()),
+        }
+    }
+
+    fn serialize_null(&mut self) -> Result<()> {
+        match self.options.null_encoding {
+            NullEncoding::Bulk => self.writer.write_all(b"$-1\r\n")?,
+            NullEncoding::Array => self.writer.write_all(b"*-1\r\n")?,
+            NullEncoding::Resp3 => self.writer.write_all(b"_\r\n")?,
+        }
+        Ok(())
+    }
+
+    // Type marker of maps and structs, and the number of header entries for each of their
+    // entries: RESP2 arrays hold keys and values flat, RESP3 maps count pairs.
+    fn map_header(&self) -> (char, usize) {
+        match self.options.protocol {
+            Protocol::Resp2 => ('*', 2),
+            Protocol::Resp3 => ('%', 1),
+        }
+    }
+
+    // Start an aggregate whose number of elements may not be known yet. Each element counts
+    // `weight` entries in the header, see map_header.
+    fn start_aggregate(&mut self, marker: char, len: Option<usize>, weight: usize) -> Result<Aggregate<'_, W>> {
+        match (len, self.options.unknown_length) {
+            (Some(len), _) => {
+                write!(self.writer, "{}{}\r\n", marker, weight * len)?;
+                Ok(Aggregate::Known(self))
+            }
+            (None, UnknownLength::Buffer) => {
+                let buf = Serializer::new(Vec::new()).with_options(self.options);
+                Ok(Aggregate::Buffered { ser: self, buf, marker, len: 0, weight })
+            }
+            (None, UnknownLength::Stream) => {
+                write!(self.writer, "{}?\r\n", marker)?;
+                Ok(Aggregate::Streamed(self))
+            }
+        }
+    }
+}
+
+// Format a double the way Redis parses it: "inf" or "-inf" for infinity, otherwise the shortest
+// representation that round-trips, using an exponent below 1e-4 or from 1e17 on.
+fn format_double<T: Float + Display + fmt::LowerExp>(v: T) -> String {
+    if v.is_infinite() {
+        return if v > T::zero() { "inf".to_owned() } else { "-inf".to_owned() };
+    }
+    let abs = v.abs();
+    if abs != T::zero() && (abs < T::from(1e-4).unwrap() || abs >= T::from(1e17).unwrap()) {
+        format!("{:e}", v)
+    } else {
+        v.to_string()
+    }
+}
+
+// Serialise into the given writer, for example a TcpStream or a BufWriter.
+pub fn to_writer<W, T>(writer: &mut W, value: &T) -> Result<()> where W: io::Write, T: ?Sized + Serialize {
+    let mut serializer = Serializer::new(writer);
+    value.serialize(&mut serializer)
+}
+
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>> where T: ?Sized + Serialize {
+    let mut output = Vec::new();
+    to_writer(&mut output, value)?;
+    Ok(output)
+}
+
+pub fn to_string<T>(value: &T) -> Result<String> where T: ?Sized + Serialize {
+    let output = to_vec(value)?;
+    String::from_utf8(output).map_err(|_| ErrorKind::SerError("output is not valid UTF-8".to_owned()).into())
+}
+
+impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
     type Ok = ();
 
     type Error = Error;
 
     // Serialise into RESP bulk strings.
     // true is encoded into "$4\r\ntrue\r\n" and false into "$5\r\nfalse\r\n".
+    // With BoolEncoding::Integer they are encoded as the integers 1 and 0, and with
+    // BoolEncoding::Resp3 into "#t\r\n" and "#f\r\n".
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
-        match v {
-            true => self.output += &format!("$4\r\ntrue\r\n"),
-            false => self.output += &format!("$5\r\nfalse\r\n"),
+        match (self.options.bool_encoding, v) {
+            (BoolEncoding::Text, true) => self.writer.write_all(b"$4\r\ntrue\r\n")?,
+            (BoolEncoding::Text, false) => self.writer.write_all(b"$5\r\nfalse\r\n")?,
+            (BoolEncoding::Integer, v) => self.serialize_integer(v as u8)?,
+            (BoolEncoding::Resp3, true) => self.writer.write_all(b"#t\r\n")?,
+            (BoolEncoding::Resp3, false) => self.writer.write_all(b"#f\r\n")?,
         }
         Ok(())
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "-100" is encoded
-    // into "$4\r\n-100\r\n".
+    // into "$4\r\n-100\r\n", or into ":-100\r\n" with IntegerEncoding::Native.
     fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "-100" is encoded
-    // into "$4\r\n-100\r\n".
+    // into "$4\r\n-100\r\n", or into ":-100\r\n" with IntegerEncoding::Native.
     fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "-100" is encoded
-    // into "$4\r\n-100\r\n".
+    // into "$4\r\n-100\r\n", or into ":-100\r\n" with IntegerEncoding::Native.
     fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "-100" is encoded
-    // into "$4\r\n-100\r\n".
+    // into "$4\r\n-100\r\n", or into ":-100\r\n" with IntegerEncoding::Native.
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "100" is encoded
-    // into "$3\r\n100\r\n".
+    // into "$3\r\n100\r\n", or into ":100\r\n" with IntegerEncoding::Native.
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "100" is encoded
-    // into "$3\r\n100\r\n".
+    // into "$3\r\n100\r\n", or into ":100\r\n" with IntegerEncoding::Native.
     fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "100" is encoded
-    // into "$3\r\n100\r\n".
+    // into "$3\r\n100\r\n", or into ":100\r\n" with IntegerEncoding::Native.
     fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_integer(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP integers with IntegerEncoding::Native.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "100" is encoded
-    // into "$3\r\n100\r\n".
+    // into "$3\r\n100\r\n", or into ":100\r\n" with IntegerEncoding::Native.
+    // RESP integers are signed 64 bit, larger values are encoded into bulk strings, or RESP3 big
+    // numbers with BigNumberEncoding::Resp3.
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_big_integer(v, v <= i64::MAX as u64)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise like i64, values outside the i64 range are encoded into bulk strings, or RESP3 big
+    // numbers such as "(-170141183460469231731687303715884105728\r\n" with BigNumberEncoding::Resp3.
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        self.serialize_big_integer(v, v >= i64::MIN as i128 && v <= i64::MAX as i128)
+    }
+
+    // Serialise like u64, values above i64::MAX are encoded into bulk strings, or RESP3 big numbers
+    // such as "(340282366920938463463374607431768211455\r\n" with BigNumberEncoding::Resp3.
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        self.serialize_big_integer(v, v <= i64::MAX as u128)
+    }
+
+    // Serialise into RESP bulk strings, or RESP3 doubles with FloatEncoding::Resp3.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "1.34" is encoded
-    // into "$4\r\n1.34\r\n".
+    // into "$4\r\n1.34\r\n", or into ",1.34\r\n" with FloatEncoding::Resp3.
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_float(v)
     }
 
-    // Serialise into RESP bulk strings.
+    // Serialise into RESP bulk strings, or RESP3 doubles with FloatEncoding::Resp3.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "1.34" is encoded
-    // into "$4\r\n1.34\r\n".
+    // into "$4\r\n1.34\r\n", or into ",1.34\r\n" with FloatEncoding::Resp3.
     fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
-        self.output += &self.serialize_num_types(v);
-        Ok(())
+        self.serialize_float(v)
     }
 
     // Serialise into RESP bulk strings.
     // Encoded format is "$<number-of-bytes>\r\n<string-data>\r\n", for example "a" is encoded
     // into "$1\r\na\r\n".
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
-        self.output += &format!("$1\r\n{}\r\n", v);
-        Ok(())
+        let mut buf = [0; 4];
+        self.write_bulk(v.encode_utf8(&mut buf).as_bytes())
     }
 
     // Serialise into RESP bulk string.
@@ -131,118 +460,251 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     // encoded into "$6\r\nfoobar\r\n".
     // Empty string is encoded into "$0\r\n\r\n".
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        self.output += &format!("${}\r\n{}\r\n", v.len(), v);
-        Ok(())
+        self.write_bulk(v.as_bytes())
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok> {
-        Err(ErrorKind::UnsupportedOperation("".to_owned()).into())
+    // Serialise into RESP bulk string, which is binary-safe.
+    // The encoded form is "$<number-of-bytes>\r\n<bytes>\r\n", for example [0, 255] is encoded
+    // into "$2\r\n\x00\xff\r\n".
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
+        self.write_bulk(v)
     }
 
     // Serialise into RESP bulk string representation of null.
-    // The encoded form is "$-1\r\n".
+    // The encoded form is "$-1\r\n", or "*-1\r\n" with NullEncoding::Array.
     fn serialize_none(self) -> Result<Self::Ok> {
-        self.output += &self.serialize_null();
-        Ok(())
+        self.serialize_null()
     }
 
-    fn serialize_some<T: ? Sized>(self, value: &T) -> Result<Self::Ok> where
-        T: Serialize {
-        let _result = value.serialize(self);
-        Ok(())
+    // Serialise the value itself, Some("foo") is encoded like "foo".
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok> where
+        T: ?Sized + Serialize {
+        value.serialize(self)
     }
 
     // Serialise into RESP bulk string representation of null.
-    // The encoded form is "$-1\r\n".
+    // The encoded form is "$-1\r\n", or "*-1\r\n" with NullEncoding::Array.
     fn serialize_unit(self) -> Result<Self::Ok> {
-        self.output += &self.serialize_null();
-        Ok(())
+        self.serialize_null()
     }
 
+    // Serialise into RESP bulk string representation of null, like unit.
+    // The encoded form is "$-1\r\n", or "*-1\r\n" with NullEncoding::Array.
     fn serialize_unit_struct(self, _name: &str) -> Result<Self::Ok> {
-        Err(ErrorKind::UnsupportedOperation("serialize_unit_struct".to_owned()).into())
+        self.serialize_null()
     }
 
-    fn serialize_unit_variant(self, _name: &str, _variant_index: u32, _variant: &str) -> Result<Self::Ok> {
-        Err(ErrorKind::UnsupportedOperation("serialize_unit_variant".to_owned()).into())
+    // Serialise into RESP bulk string of the variant name.
+    // For example Status::Active is encoded into "$6\r\nActive\r\n".
+    fn serialize_unit_variant(self, _name: &str, _variant_index: u32, variant: &str) -> Result<Self::Ok> {
+        self.write_bulk(variant.as_bytes())
     }
 
-    fn serialize_newtype_struct<T: ? Sized>(self, _name: &str, _value: &T) -> Result<Self::Ok> where
-        T: Serialize {
-        Err(ErrorKind::UnsupportedOperation("serialize_newtype_struct".to_owned()).into())
+    // Serialise transparently as the wrapped value, for example UserId(100) is encoded into
+    // "$3\r\n100\r\n". A SimpleString is serialised into RESP simple string "+<string-data>\r\n",
+    // Set and Verbatim into their RESP3 types with Protocol::Resp3.
+    fn serialize_newtype_struct<T>(self, name: &str, value: &T) -> Result<Self::Ok> where
+        T: ?Sized + Serialize {
+        match name {
+            SIMPLE_STRING_TOKEN => value.serialize(RestrictedSerializer { ser: self, kind: Restricted::SimpleString }),
+            SET_TOKEN => value.serialize(RestrictedSerializer { ser: self, kind: Restricted::Set }),
+            _ => value.serialize(self),
+        }
     }
 
-    fn serialize_newtype_variant<T: ? Sized>(self, _name: &str, _variant_index: u32, _variant: &str, _value: &T) -> Result<Self::Ok> where
-        T: Serialize {
-        Err(ErrorKind::UnsupportedOperation("serialize_newtype_variant".to_owned()).into())
+    // Serialise into RESP array of the variant name and the value.
+    // For example Message::Text("foo") is encoded into "*2\r\n$4\r\nText\r\n$3\r\nfoo\r\n".
+    // A Verbatim carries its format as the variant name and is serialised into its RESP3 type with
+    // Protocol::Resp3.
+    fn serialize_newtype_variant<T>(self, name: &str, _variant_index: u32, variant: &str, value: &T) -> Result<Self::Ok> where
+        T: ?Sized + Serialize {
+        if name == VERBATIM_TOKEN {
+            return value.serialize(RestrictedSerializer { ser: self, kind: Restricted::Verbatim(variant) });
+        }
+        self.writer.write_all(b"*2\r\n")?;
+        self.write_bulk(variant.as_bytes())?;
+        value.serialize(self)
     }
 
     // Serialise into RESP array.
     // The encoded form is "*<number-of-elements>\r\n<RESP-type-for-every-element>", for example
     // ["foo", "bar"] is encoded into "*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".
+    // When the length is unknown the elements are buffered, or streamed with UnknownLength::Stream.
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
-        // len must be known upfront.
-        if len.is_none() {
-            return Err(ErrorKind::SerError("sequence length is unknown".to_owned()).into());
-        }
-        self.output += "*";
-
-        if len.unwrap() == 0 {
-            self.output += &format!("{}\r\n", len.unwrap());
-        } else {
-            self.output += &format!("{}\r\n", len.unwrap());
-        }
-
-        Ok(self)
+        self.start_aggregate('*', len, 1)
     }
 
     // Serialise into RESP array.
     // The encoded form is "*<number-of-elements>\r\n<RESP-type-for-every-element>", for example
     // ("foo", "bar") is encoded into "*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
-        self.serialize_seq(Some(len))
+        write!(self.writer, "*{}\r\n", len)?;
+        Ok(self)
     }
 
     // Serialise into RESP array.
     // The encoded form is "*<number-of-elements>\r\n<RESP-type-for-every-element>", for example
     // Tuple("foo", "bar") is encoded into "*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".
-    fn serialize_tuple_struct(self, _name: &str, len: usize) -> Result<(Self::SerializeTupleStruct)> {
-        self.serialize_seq(Some(len))
+    fn serialize_tuple_struct(self, _name: &str, len: usize) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_tuple(len)
     }
 
-    fn serialize_tuple_variant(self, _name: &str, _variant_index: u32, _variant: &str, _len: usize)
-        -> Result<(Self::SerializeStructVariant)> {
-        Err(ErrorKind::UnsupportedOperation("serialize_tuple_variant".to_owned()).into())
+    // Serialise into RESP array of the variant name and an array of the fields.
+    // For example Point::Xy(1, 2) is encoded into
+    // "*2\r\n$2\r\nXy\r\n*2\r\n$1\r\n1\r\n$1\r\n2\r\n".
+    fn serialize_tuple_variant(self, _name: &str, _variant_index: u32, variant: &str, len: usize)
+        -> Result<Self::SerializeTupleVariant> {
+        self.writer.write_all(b"*2\r\n")?;
+        self.write_bulk(variant.as_bytes())?;
+        write!(self.writer, "*{}\r\n", len)?;
+        Ok(Compound { ser: self, remaining: len })
     }
 
-    fn serialize_map(self, _len: Option<usize>) -> Result<(Self::SerializeMap)> {
-        Err(ErrorKind::UnsupportedOperation("serialize_map".to_owned()).into())
+    // Serialise into RESP array of alternating keys and values, as expected by HSET or MSET.
+    // The encoded form is "*<2 * number-of-entries>\r\n<key><value>...", for example
+    // {"foo": "bar"} is encoded into "*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".
+    // Keys are encoded into bulk strings and must be strings, chars, integers or bools.
+    // When the length is unknown the entries are buffered, or streamed with UnknownLength::Stream.
+    // With Protocol::Resp3 the encoded form is "%<number-of-entries>\r\n<key><value>...".
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        let (marker, weight) = self.map_header();
+        self.start_aggregate(marker, len, weight)
     }
 
-    fn serialize_struct(self, _name: &str, _len: usize) -> Result<(Self::SerializeStruct)> {
-        Err(ErrorKind::UnsupportedOperation("serialize_struct".to_owned()).into())
+    // Serialise into RESP array of alternating field names and values, as expected by HSET.
+    // The encoded form is "*<2 * number-of-fields>\r\n<field-name><value>...", for example
+    // User { name: "foo" } is encoded into "*2\r\n$4\r\nname\r\n$3\r\nfoo\r\n".
+    // Skipped fields are not counted. With Protocol::Resp3 the encoded form is
+    // "%<number-of-fields>\r\n<field-name><value>...".
+    fn serialize_struct(self, _name: &str, len: usize) -> Result<Self::SerializeStruct> {
+        let (marker, weight) = self.map_header();
+        write!(self.writer, "{}{}\r\n", marker, weight * len)?;
+        Ok(Compound { ser: self, remaining: len })
     }
 
-    fn serialize_struct_variant(self, _name: &str, _variant_index: u32, _variant: &str, _len: usize)
-        -> Result<(Self::SerializeStructVariant)> {
-        Err(ErrorKind::UnsupportedOperation("serialize_struct_variant".to_owned()).into())
+    // Serialise into RESP array of the variant name and an array of alternating field names and
+    // values. For example Point::Xy { x: 1 } is encoded into
+    // "*2\r\n$2\r\nXy\r\n*2\r\n$1\r\nx\r\n$1\r\n1\r\n".
+    fn serialize_struct_variant(self, _name: &str, _variant_index: u32, variant: &str, len: usize)
+        -> Result<Self::SerializeStructVariant> {
+        self.writer.write_all(b"*2\r\n")?;
+        self.write_bulk(variant.as_bytes())?;
+        let (marker, weight) = self.map_header();
+        write!(self.writer, "{}{}\r\n", marker, weight * len)?;
+        Ok(Compound { ser: self, remaining: len })
     }
 
-    fn collect_str<T: ? Sized>(self, _value: &T) -> Result<Self::Ok> where
-        T: Display {
-        Err(ErrorKind::UnsupportedOperation("collect_str".to_owned()).into())
+    // Serialise the Display output into RESP bulk string, for example an Ipv4Addr is encoded into
+    // "$9\r\n127.0.0.1\r\n". The value is formatted once to count its bytes and once more into the
+    // writer, so no temporary String is allocated.
+    fn collect_str<T>(self, value: &T) -> Result<Self::Ok> where
+        T: ?Sized + Display {
+        let mut counter = ByteCounter(0);
+        fmt::write(&mut counter, format_args!("{}", value))
+            .map_err(|_| Error::from(ErrorKind::SerError("Display implementation returned an error".to_owned())))?;
+        write!(self.writer, "${}\r\n", counter.0)?;
+        let mut writer = CountingWriter { writer: &mut self.writer, count: 0 };
+        io::Write::write_fmt(&mut writer, format_args!("{}", value))?;
+        // The header is already written, a different length would corrupt every following frame.
+        if writer.count != counter.0 {
+            return Err(ErrorKind::SerError("Display output changed between formatting passes".to_owned()).into());
+        }
+        self.writer.write_all(b"\r\n")?;
+        Ok(())
     }
 
-    type SerializeSeq = Self;
+    type SerializeSeq = Aggregate<'a, W>;
     type SerializeTuple = Self;
     type SerializeTupleStruct = Self;
-    type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
-    type SerializeMap = Impossible<Self::Ok, Self::Error>;
-    type SerializeStruct = Impossible<Self::Ok, Self::Error>;
-    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = Aggregate<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+}
+
+// Counts the bytes of formatted output without storing it.
+struct ByteCounter(usize);
+
+impl fmt::Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+// Counts the bytes written through to the underlying writer.
+struct CountingWriter<'a, W: 'a> {
+    writer: &'a mut W,
+    count: usize,
+}
+
+impl<'a, W: io::Write> io::Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// State of a sequence or map, whose length may only be known once all elements are written.
+pub enum Aggregate<'a, W: 'a> {
+    // The header was written upfront, elements go straight to the writer.
+    Known(&'a mut Serializer<W>),
+    // Elements are written into a buffer and counted, the header is written at the end.
+    Buffered { ser: &'a mut Serializer<W>, buf: Serializer<Vec<u8>>, marker: char, len: usize, weight: usize },
+    // RESP3 streamed aggregate, terminated by ".\r\n" at the end.
+    Streamed(&'a mut Serializer<W>),
+}
+
+impl<'a, W: io::Write> Aggregate<'a, W> {
+    // Serialise a single element, counting it if the header is still to be written.
+    fn element<T>(&mut self, value: &T) -> Result<()> where T: ?Sized + Serialize {
+        match *self {
+            Aggregate::Known(ref mut ser) | Aggregate::Streamed(ref mut ser) => value.serialize(&mut **ser),
+            Aggregate::Buffered { ref mut buf, ref mut len, .. } => {
+                *len += 1;
+                value.serialize(buf)
+            }
+        }
+    }
+
+    fn key<T>(&mut self, key: &T) -> Result<()> where T: ?Sized + Serialize {
+        match *self {
+            Aggregate::Known(ref mut ser) | Aggregate::Streamed(ref mut ser) => {
+                key.serialize(MapKeySerializer { ser: &mut **ser })
+            }
+            Aggregate::Buffered { ref mut buf, ref mut len, .. } => {
+                *len += 1;
+                key.serialize(MapKeySerializer { ser: buf })
+            }
+        }
+    }
+
+    fn value<T>(&mut self, value: &T) -> Result<()> where T: ?Sized + Serialize {
+        match *self {
+            Aggregate::Known(ref mut ser) | Aggregate::Streamed(ref mut ser) => value.serialize(&mut **ser),
+            Aggregate::Buffered { ref mut buf, .. } => value.serialize(buf),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Aggregate::Known(_) => {}
+            Aggregate::Buffered { ser, buf, marker, len, weight } => {
+                write!(ser.writer, "{}{}\r\n", marker, weight * len)?;
+                ser.writer.write_all(&buf.into_inner())?;
+            }
+            Aggregate::Streamed(ser) => ser.writer.write_all(b".\r\n")?,
+        }
+        Ok(())
+    }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl<'a, W: io::Write> ser::SerializeSeq for Aggregate<'a, W> {
     // Must match the `Ok` type of the serializer.
     type Ok = ();
     // Must match the `Error` type of the serializer.
@@ -252,16 +714,16 @@ impl<'a> ser::SerializeSeq for &'a mut Serializer {
     fn serialize_element<T>(&mut self, value: &T) -> Result<()>
         where T: ?Sized + Serialize
     {
-        value.serialize(&mut **self)
+        self.element(value)
     }
 
     // Close the sequence.
     fn end(self) -> Result<()> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl<W: io::Write> ser::SerializeTuple for &mut Serializer<W> {
     // Must match the `Ok` type of the serializer.
     type Ok = ();
     // Must match the `Error` type of the serializer.
@@ -280,7 +742,7 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl<W: io::Write> ser::SerializeTupleStruct for &mut Serializer<W> {
     // Must match the `Ok` type of the serializer.
     type Ok = ();
     // Must match the `Error` type of the serializer.
@@ -298,12 +760,544 @@ impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
     }
 }
 
+impl<'a, W: io::Write> ser::SerializeMap for Aggregate<'a, W> {
+    // Must match the `Ok` type of the serializer.
+    type Ok = ();
+    // Must match the `Error` type of the serializer.
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        self.key(key)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        self.value(value)
+    }
+
+    // Close the map.
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+// State of a compound value whose number of elements was written upfront.
+pub struct Compound<'a, W: 'a> {
+    ser: &'a mut Serializer<W>,
+    remaining: usize,
+}
+
+impl<'a, W: io::Write> ser::SerializeStruct for Compound<'a, W> {
+    // Must match the `Ok` type of the serializer.
+    type Ok = ();
+    // Must match the `Error` type of the serializer.
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        if self.remaining == 0 {
+            return Err(ErrorKind::SerError("more fields than the struct length".to_owned()).into());
+        }
+        self.remaining -= 1;
+        self.ser.write_bulk(key.as_bytes())?;
+        value.serialize(&mut *self.ser)
+    }
+
+    // Close the struct, the header must have announced exactly the fields written.
+    fn end(self) -> Result<()> {
+        if self.remaining != 0 {
+            return Err(ErrorKind::SerError("fewer fields than the struct length".to_owned()).into());
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeTupleVariant for Compound<'a, W> {
+    // Must match the `Ok` type of the serializer.
+    type Ok = ();
+    // Must match the `Error` type of the serializer.
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        if self.remaining == 0 {
+            return Err(ErrorKind::SerError("more fields than the tuple variant length".to_owned()).into());
+        }
+        self.remaining -= 1;
+        value.serialize(&mut *self.ser)
+    }
+
+    // Close the variant, the header must have announced exactly the fields written.
+    fn end(self) -> Result<()> {
+        if self.remaining != 0 {
+            return Err(ErrorKind::SerError("fewer fields than the tuple variant length".to_owned()).into());
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: io::Write> ser::SerializeStructVariant for Compound<'a, W> {
+    // Must match the `Ok` type of the serializer.
+    type Ok = ();
+    // Must match the `Error` type of the serializer.
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        ser::SerializeStruct::end(self)
+    }
+}
+
+// Serializer for map keys, which are always encoded into bulk strings.
+struct MapKeySerializer<'a, W: 'a> {
+    ser: &'a mut Serializer<W>,
+}
+
+fn map_key_error() -> Error {
+    ErrorKind::SerError("map key must be a string, char, integer, bool or unit variant".to_owned()).into()
+}
+
+impl<'a, W: io::Write> ser::Serializer for MapKeySerializer<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    // Keys are always bulk strings, "1" and "0" with BoolEncoding::Integer.
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        match self.ser.options.bool_encoding {
+            BoolEncoding::Integer => self.ser.serialize_num_types(v as u8),
+            BoolEncoding::Text | BoolEncoding::Resp3 => self.ser.write_bulk(if v { b"true" } else { b"false" }),
+        }
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        self.ser.serialize_num_types(v)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<()> {
+        Err(map_key_error())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<()> {
+        Err(map_key_error())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        let mut buf = [0; 4];
+        self.ser.write_bulk(v.encode_utf8(&mut buf).as_bytes())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.ser.write_bulk(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.ser.write_bulk(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Err(map_key_error())
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<()> where T: ?Sized + Serialize {
+        Err(map_key_error())
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Err(map_key_error())
+    }
+
+    fn serialize_unit_struct(self, _name: &str) -> Result<()> {
+        Err(map_key_error())
+    }
+
+    fn serialize_unit_variant(self, _name: &str, _variant_index: u32, variant: &str) -> Result<()> {
+        self.ser.write_bulk(variant.as_bytes())
+    }
+
+    // Keys wrapped into a newtype, such as struct UserId(u64), are encoded as their content.
+    fn serialize_newtype_struct<T>(self, _name: &str, value: &T) -> Result<()> where T: ?Sized + Serialize {
+        value.serialize(self)
+    }
+
+    // A Verbatim key is encoded as its text.
+    fn serialize_newtype_variant<T>(self, name: &str, _variant_index: u32, _variant: &str, value: &T)
+        -> Result<()> where T: ?Sized + Serialize {
+        if name == VERBATIM_TOKEN {
+            return value.serialize(self);
+        }
+        Err(map_key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(map_key_error())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(map_key_error())
+    }
+
+    fn serialize_tuple_struct(self, _name: &str, _len: usize) -> Result<Self::SerializeTupleStruct> {
+        Err(map_key_error())
+    }
+
+    fn serialize_tuple_variant(self, _name: &str, _variant_index: u32, _variant: &str, _len: usize)
+        -> Result<Self::SerializeTupleVariant> {
+        Err(map_key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(map_key_error())
+    }
+
+    fn serialize_struct(self, _name: &str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(map_key_error())
+    }
+
+    fn serialize_struct_variant(self, _name: &str, _variant_index: u32, _variant: &str, _len: usize)
+        -> Result<Self::SerializeStructVariant> {
+        Err(map_key_error())
+    }
+}
+
+const SIMPLE_STRING_TOKEN: &str = "$serde_resp::private::SimpleString";
+
+// A string serialised into RESP simple string, for status replies such as "+OK\r\n" or
+// "+PONG\r\n". The string must not contain '\r' or '\n'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleString<S>(pub S);
+
+impl<S> Serialize for SimpleString<S> where S: AsRef<str> {
+    fn serialize<T>(&self, serializer: T) -> ::std::result::Result<T::Ok, T::Error>
+        where T: ser::Serializer
+    {
+        serializer.serialize_newtype_struct(SIMPLE_STRING_TOKEN, self.0.as_ref())
+    }
+}
+
+impl<'de, S> Deserialize<'de> for SimpleString<S> where S: Deserialize<'de> {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        S::deserialize(deserializer).map(SimpleString)
+    }
+}
+
+const SET_TOKEN: &str = "$serde_resp::private::Set";
+
+// A collection serialised into RESP3 set "~<number-of-elements>\r\n...", for example a
+// BTreeSet or HashSet, with Protocol::Resp3. It is an array in RESP2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Set<T>(pub T);
+
+impl<T> Serialize for Set<T> where T: Serialize {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        serializer.serialize_newtype_struct(SET_TOKEN, &self.0)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Set<T> where T: Deserialize<'de> {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        T::deserialize(deserializer).map(Set)
+    }
+}
+
+const VERBATIM_TOKEN: &str = "$serde_resp::private::Verbatim";
+
+// A string serialised into RESP3 verbatim string "=<number-of-bytes>\r\n<format>:<text>\r\n"
+// with Protocol::Resp3, as in replies to INFO or LATENCY DOCTOR. The format is 3 bytes long, such
+// as "txt" or "mkd". It is a bulk string of the text in RESP2.
+// Verbatim is serialize-only, unlike SimpleString and Set: the deserializer strips the format of
+// verbatim strings and RESP2 carries none, so the format could not be restored. Deserialise the
+// text into a String or &str field instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verbatim<S> {
+    pub format: &'static str,
+    pub text: S,
+}
+
+impl<S> Verbatim<S> {
+    // Plain text, "txt".
+    pub fn text(text: S) -> Self {
+        Verbatim { format: "txt", text }
+    }
+
+    // Markdown, "mkd".
+    pub fn markdown(text: S) -> Self {
+        Verbatim { format: "mkd", text }
+    }
+}
+
+impl<S> Serialize for Verbatim<S> where S: AsRef<str> {
+    fn serialize<T>(&self, serializer: T) -> ::std::result::Result<T::Ok, T::Error>
+        where T: ser::Serializer
+    {
+        serializer.serialize_newtype_variant(VERBATIM_TOKEN, 0, self.format, self.text.as_ref())
+    }
+}
+
+// The wrapper type whose content a RestrictedSerializer serialises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Restricted<'a> {
+    SimpleString,
+    Set,
+    // The format of the verbatim string, such as "txt".
+    Verbatim(&'a str),
+}
+
+impl<'a> Restricted<'a> {
+    fn error(self) -> Error {
+        let message = match self {
+            Restricted::SimpleString => "simple string must be a string",
+            Restricted::Set => "set must be a sequence",
+            Restricted::Verbatim(_) => "verbatim string must be a string",
+        };
+        ErrorKind::SerError(message.to_owned()).into()
+    }
+}
+
+// Serializer for the content of a SimpleString, a Verbatim or a Set. The first two only accept
+// strings and the last one only sequences.
+struct RestrictedSerializer<'a, W: 'a> {
+    ser: &'a mut Serializer<W>,
+    kind: Restricted<'a>,
+}
+
+impl<'a, W: io::Write> ser::Serializer for RestrictedSerializer<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Aggregate<'a, W>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    // Serialise a SimpleString into RESP simple string "+<string-data>\r\n", for example "OK" is
+    // encoded into "+OK\r\n". Serialise the text of a Verbatim into RESP3 verbatim string
+    // "=<number-of-bytes>\r\n<format>:<text>\r\n", or into RESP bulk string of the text only with
+    // Protocol::Resp2.
+    fn serialize_str(self, v: &str) -> Result<()> {
+        match self.kind {
+            Restricted::SimpleString => {
+                if v.contains(['\r', '\n']) {
+                    return Err(ErrorKind::SerError("simple string must not contain '\\r' or '\\n'".to_owned()).into());
+                }
+                write!(self.ser.writer, "+{}\r\n", v)?;
+                Ok(())
+            }
+            Restricted::Verbatim(format) => {
+                if format.len() != 3 {
+                    return Err(ErrorKind::SerError("verbatim string format must be 3 bytes long".to_owned()).into());
+                }
+                match self.ser.options.protocol {
+                    Protocol::Resp2 => self.ser.write_bulk(v.as_bytes()),
+                    Protocol::Resp3 => {
+                        write!(self.ser.writer, "={}\r\n{}:{}\r\n", v.len() + 4, format, v)?;
+                        Ok(())
+                    }
+                }
+            }
+            Restricted::Set => Err(self.kind.error()),
+        }
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_i128(self, _v: i128) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_u128(self, _v: u128) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_char(self, _v: char) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<()> where T: ?Sized + Serialize {
+        Err(self.kind.error())
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_unit_struct(self, _name: &str) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_unit_variant(self, _name: &str, _variant_index: u32, _variant: &str) -> Result<()> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &str, _value: &T) -> Result<()> where T: ?Sized + Serialize {
+        Err(self.kind.error())
+    }
+
+    fn serialize_newtype_variant<T>(self, _name: &str, _variant_index: u32, _variant: &str, _value: &T)
+        -> Result<()> where T: ?Sized + Serialize {
+        Err(self.kind.error())
+    }
+
+    // Serialise a Set into RESP3 set "~<number-of-elements>\r\n<RESP-type-for-every-element>", or
+    // into RESP array with Protocol::Resp2.
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        if self.kind != Restricted::Set {
+            return Err(self.kind.error());
+        }
+        let marker = match self.ser.options.protocol {
+            Protocol::Resp2 => '*',
+            Protocol::Resp3 => '~',
+        };
+        self.ser.start_aggregate(marker, len, 1)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_tuple_struct(self, _name: &str, _len: usize) -> Result<Self::SerializeTupleStruct> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_tuple_variant(self, _name: &str, _variant_index: u32, _variant: &str, _len: usize)
+        -> Result<Self::SerializeTupleVariant> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_struct(self, _name: &str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(self.kind.error())
+    }
+
+    fn serialize_struct_variant(self, _name: &str, _variant_index: u32, _variant: &str, _len: usize)
+        -> Result<Self::SerializeStructVariant> {
+        Err(self.kind.error())
+    }
+}
+
 
 ///////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use serde_bytes::{ByteBuf, Bytes};
+    use std::collections::{BTreeMap, HashMap};
 
     #[test]
     fn test_serialize_bool() {
@@ -313,63 +1307,64 @@ mod test {
 
     #[test]
     fn test_serialize_i8() {
-        assert_eq!(to_string(&(-100 as i8)).unwrap(), "$4\r\n-100\r\n");
-        assert_eq!(to_string(&(100 as i8)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&-100_i8).unwrap(), "$4\r\n-100\r\n");
+        assert_eq!(to_string(&100_i8).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_i16() {
-        assert_eq!(to_string(&(-100 as i8)).unwrap(), "$4\r\n-100\r\n");
-        assert_eq!(to_string(&(100 as i8)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&-100_i8).unwrap(), "$4\r\n-100\r\n");
+        assert_eq!(to_string(&100_i8).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_i32() {
-        assert_eq!(to_string(&(-100 as i8)).unwrap(), "$4\r\n-100\r\n");
-        assert_eq!(to_string(&(100 as i8)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&-100_i8).unwrap(), "$4\r\n-100\r\n");
+        assert_eq!(to_string(&100_i8).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_i64() {
-        assert_eq!(to_string(&(-100 as i8)).unwrap(), "$4\r\n-100\r\n");
-        assert_eq!(to_string(&(100 as i8)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&-100_i8).unwrap(), "$4\r\n-100\r\n");
+        assert_eq!(to_string(&100_i8).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_u8() {
-        assert_eq!(to_string(&(100 as u8)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&100_u8).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_u16() {
-        assert_eq!(to_string(&(100 as u16)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&100_u16).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_u32() {
-        assert_eq!(to_string(&(100 as u32)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&100_u32).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_u64() {
-        assert_eq!(to_string(&(100 as u64)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&100_u64).unwrap(), "$3\r\n100\r\n");
     }
 
     #[test]
     fn test_serialize_f32() {
-        assert_eq!(to_string(&(-1.34 as f32)).unwrap(), "$5\r\n-1.34\r\n");
-        assert_eq!(to_string(&(1.34 as f32)).unwrap(), "$4\r\n1.34\r\n");
+        assert_eq!(to_string(&-1.34_f32).unwrap(), "$5\r\n-1.34\r\n");
+        assert_eq!(to_string(&1.34_f32).unwrap(), "$4\r\n1.34\r\n");
     }
 
     #[test]
     fn test_serialize_f64() {
-        assert_eq!(to_string(&(-1.34 as f64)).unwrap(), "$5\r\n-1.34\r\n");
-        assert_eq!(to_string(&(1.34 as f64)).unwrap(), "$4\r\n1.34\r\n");
+        assert_eq!(to_string(&-1.34_f64).unwrap(), "$5\r\n-1.34\r\n");
+        assert_eq!(to_string(&1.34_f64).unwrap(), "$4\r\n1.34\r\n");
     }
 
     #[test]
     fn test_serialize_char() {
         assert_eq!(to_string(&'a').unwrap(), "$1\r\na\r\n");
+        assert_eq!(to_string(&'é').unwrap(), "$2\r\né\r\n");
     }
 
     #[test]
@@ -386,36 +1381,36 @@ mod test {
 
         // signed int
         assert_eq!(to_string(&(Vec::new() as Vec<i8>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as i8, 2 as i8]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_i8, 2_i8]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         assert_eq!(to_string(&(Vec::new() as Vec<i16>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as i16, 2 as i16]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_i16, 2_i16]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         assert_eq!(to_string(&(Vec::new() as Vec<i32>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as i32, 2 as i32]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_i32, 2_i32]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         assert_eq!(to_string(&(Vec::new() as Vec<i64>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as i64, 2 as i64]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_i64, 2_i64]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         // unsigned int
         assert_eq!(to_string(&(Vec::new() as Vec<u8>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as u8, 2 as u8]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_u8, 2_u8]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         assert_eq!(to_string(&(Vec::new() as Vec<u16>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as u16, 2 as u16]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_u16, 2_u16]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         assert_eq!(to_string(&(Vec::new() as Vec<u32>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as u32, 2 as u32]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_u32, 2_u32]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         assert_eq!(to_string(&(Vec::new() as Vec<u64>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as u64, 2 as u64]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&vec![1_u64, 2_u64]).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
 
         // float
         assert_eq!(to_string(&(Vec::new() as Vec<f32>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as f32, 2.14 as f32]).unwrap(), "*2\r\n$1\r\n1\r\n$4\r\n2.14\r\n");
+        assert_eq!(to_string(&vec![1_f32, 2.14_f32]).unwrap(), "*2\r\n$1\r\n1\r\n$4\r\n2.14\r\n");
 
         assert_eq!(to_string(&(Vec::new() as Vec<f64>)).unwrap(), "*0\r\n");
-        assert_eq!(to_string(&vec![1 as f64, 2.14 as f64]).unwrap(), "*2\r\n$1\r\n1\r\n$4\r\n2.14\r\n");
+        assert_eq!(to_string(&vec![1_f64, 2.14_f64]).unwrap(), "*2\r\n$1\r\n1\r\n$4\r\n2.14\r\n");
 
         // char
         assert_eq!(to_string(&(Vec::new() as Vec<char>)).unwrap(), "*0\r\n");
@@ -448,25 +1443,30 @@ mod test {
 
     #[test]
     fn test_serialize_num_types() {
-        let ser = Serializer { output: "".to_owned() };
-        assert_eq!(ser.serialize_num_types(100 as i8), "$3\r\n100\r\n");
-        assert_eq!(ser.serialize_num_types(100 as i16), "$3\r\n100\r\n");
-        assert_eq!(ser.serialize_num_types(100 as i32), "$3\r\n100\r\n");
-        assert_eq!(ser.serialize_num_types(100 as i64), "$3\r\n100\r\n");
-
-        assert_eq!(ser.serialize_num_types(100 as u8), "$3\r\n100\r\n");
-        assert_eq!(ser.serialize_num_types(100 as u16), "$3\r\n100\r\n");
-        assert_eq!(ser.serialize_num_types(100 as u32), "$3\r\n100\r\n");
-        assert_eq!(ser.serialize_num_types(100 as u64), "$3\r\n100\r\n");
-
-        assert_eq!(ser.serialize_num_types(3.14 as f32), "$4\r\n3.14\r\n");
-        assert_eq!(ser.serialize_num_types(3.14 as f64), "$4\r\n3.14\r\n");
+        fn serialize_num_types<T: Num + Display>(v: T) -> Vec<u8> {
+            let mut ser = Serializer::new(Vec::new());
+            ser.serialize_num_types(v).unwrap();
+            ser.into_inner()
+        }
+        assert_eq!(serialize_num_types(100_i8), b"$3\r\n100\r\n");
+        assert_eq!(serialize_num_types(100_i16), b"$3\r\n100\r\n");
+        assert_eq!(serialize_num_types(100_i32), b"$3\r\n100\r\n");
+        assert_eq!(serialize_num_types(100_i64), b"$3\r\n100\r\n");
+
+        assert_eq!(serialize_num_types(100_u8), b"$3\r\n100\r\n");
+        assert_eq!(serialize_num_types(100_u16), b"$3\r\n100\r\n");
+        assert_eq!(serialize_num_types(100_u32), b"$3\r\n100\r\n");
+        assert_eq!(serialize_num_types(100_u64), b"$3\r\n100\r\n");
+
+        assert_eq!(serialize_num_types(2.75_f32), b"$4\r\n2.75\r\n");
+        assert_eq!(serialize_num_types(2.75_f64), b"$4\r\n2.75\r\n");
     }
 
     #[test]
     fn test_serialize_null() {
-        let ser = Serializer { output: "".to_owned() };
-        assert_eq!(ser.serialize_null(), "$-1\r\n");
+        let mut ser = Serializer::new(Vec::new());
+        ser.serialize_null().unwrap();
+        assert_eq!(ser.into_inner(), b"$-1\r\n");
     }
 
     #[test]
@@ -500,18 +1500,18 @@ mod test {
     fn test_serialize_some() {
         assert_eq!(to_string(&Some(true)).unwrap(), "$4\r\ntrue\r\n");
 
-        assert_eq!(to_string(&Some(-1 as i8)).unwrap(), "$2\r\n-1\r\n");
-        assert_eq!(to_string(&Some(-1 as i16)).unwrap(), "$2\r\n-1\r\n");
-        assert_eq!(to_string(&Some(-100 as i32)).unwrap(), "$4\r\n-100\r\n");
-        assert_eq!(to_string(&Some(-100 as i64)).unwrap(), "$4\r\n-100\r\n");
+        assert_eq!(to_string(&Some(-1_i8)).unwrap(), "$2\r\n-1\r\n");
+        assert_eq!(to_string(&Some(-1_i16)).unwrap(), "$2\r\n-1\r\n");
+        assert_eq!(to_string(&Some(-100_i32)).unwrap(), "$4\r\n-100\r\n");
+        assert_eq!(to_string(&Some(-100_i64)).unwrap(), "$4\r\n-100\r\n");
 
-        assert_eq!(to_string(&Some(1 as u8)).unwrap(), "$1\r\n1\r\n");
-        assert_eq!(to_string(&Some(1 as u16)).unwrap(), "$1\r\n1\r\n");
-        assert_eq!(to_string(&Some(100 as u32)).unwrap(), "$3\r\n100\r\n");
-        assert_eq!(to_string(&Some(100 as u64)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&Some(1_u8)).unwrap(), "$1\r\n1\r\n");
+        assert_eq!(to_string(&Some(1_u16)).unwrap(), "$1\r\n1\r\n");
+        assert_eq!(to_string(&Some(100_u32)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&Some(100_u64)).unwrap(), "$3\r\n100\r\n");
 
-        assert_eq!(to_string(&Some(-1.34 as f32)).unwrap(), "$5\r\n-1.34\r\n");
-        assert_eq!(to_string(&Some(-1.34 as f64)).unwrap(), "$5\r\n-1.34\r\n");
+        assert_eq!(to_string(&Some(-1.34_f32)).unwrap(), "$5\r\n-1.34\r\n");
+        assert_eq!(to_string(&Some(-1.34_f64)).unwrap(), "$5\r\n-1.34\r\n");
 
         assert_eq!(to_string(&Some('a')).unwrap(), "$1\r\na\r\n");
 
@@ -528,4 +1528,498 @@ mod test {
         );
         assert_eq!(to_string(&Some(Tuple("mykey", (10, 'a')))).unwrap(), "*2\r\n$5\r\nmykey\r\n*2\r\n$2\r\n10\r\n$1\r\na\r\n");
     }
+
+    #[test]
+    fn test_to_writer() {
+        let mut output = Vec::new();
+        to_writer(&mut output, &("SET", "mykey", 10)).unwrap();
+        to_writer(&mut output, &("GET", "mykey")).unwrap();
+        assert_eq!(output, &b"*3\r\n$3\r\nSET\r\n$5\r\nmykey\r\n$2\r\n10\r\n*2\r\n$3\r\nGET\r\n$5\r\nmykey\r\n"[..]);
+
+        let mut writer = io::BufWriter::new(Vec::new());
+        to_writer(&mut writer, &"foobar").unwrap();
+        assert_eq!(writer.into_inner().unwrap(), b"$6\r\nfoobar\r\n");
+
+        let mut ser = Serializer::new(Vec::new());
+        vec!["foo", "bar"].serialize(&mut ser).unwrap();
+        assert_eq!(ser.into_inner(), b"*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n");
+
+        let mut buf = [0; 4];
+        assert!(to_writer(&mut &mut buf[..], &"foobar").is_err());
+    }
+
+    #[test]
+    fn test_to_vec() {
+        assert_eq!(to_vec(&"foobar").unwrap(), b"$6\r\nfoobar\r\n");
+        assert_eq!(to_vec(&vec![1, 2]).unwrap(), b"*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+    }
+
+    #[test]
+    fn test_serialize_bytes() {
+        assert_eq!(to_vec(&ByteBuf::from(vec![0, 255, 13, 10])).unwrap(), b"$4\r\n\x00\xff\r\n\r\n");
+        assert_eq!(to_vec(&Bytes::new(b"")).unwrap(), b"$0\r\n\r\n");
+        assert_eq!(to_string(&Bytes::new(b"foobar")).unwrap(), "$6\r\nfoobar\r\n");
+        assert!(to_string(&Bytes::new(b"\xff")).is_err());
+
+        #[derive(Serialize)]
+        struct Tuple<'a>(
+            &'a str,
+            #[serde(with = "serde_bytes")]
+            &'a [u8]
+        );
+        assert_eq!(to_vec(&Tuple("mykey", b"\x00\x01")).unwrap(), b"*2\r\n$5\r\nmykey\r\n$2\r\n\x00\x01\r\n");
+    }
+
+    fn to_string_with<T: Serialize>(options: SerializerOptions, value: &T) -> Result<String> {
+        let mut ser = Serializer::new(Vec::new()).with_options(options);
+        value.serialize(&mut ser)?;
+        Ok(String::from_utf8(ser.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn test_integer_encoding() {
+        fn to_string_native<T: Serialize>(value: &T) -> String {
+            to_string_with(SerializerOptions::new().integer_encoding(IntegerEncoding::Native), value).unwrap()
+        }
+
+        assert_eq!(to_string_native(&-100_i8), ":-100\r\n");
+        assert_eq!(to_string_native(&-100_i16), ":-100\r\n");
+        assert_eq!(to_string_native(&-100_i32), ":-100\r\n");
+        assert_eq!(to_string_native(&i64::MIN), ":-9223372036854775808\r\n");
+        assert_eq!(to_string_native(&100_u8), ":100\r\n");
+        assert_eq!(to_string_native(&100_u16), ":100\r\n");
+        assert_eq!(to_string_native(&100_u32), ":100\r\n");
+        assert_eq!(to_string_native(&(i64::MAX as u64)), ":9223372036854775807\r\n");
+        assert_eq!(to_string_native(&u64::MAX), "$20\r\n18446744073709551615\r\n");
+
+        assert_eq!(to_string_native(&vec![1, 2]), "*2\r\n:1\r\n:2\r\n");
+        assert_eq!(to_string_native(&("mykey", 10)), "*2\r\n$5\r\nmykey\r\n:10\r\n");
+        assert_eq!(to_string_native(&1.34), "$4\r\n1.34\r\n");
+
+        assert_eq!(to_string(&-100_i64).unwrap(), "$4\r\n-100\r\n");
+    }
+
+    #[test]
+    fn test_serialize_simple_string() {
+        assert_eq!(to_string(&SimpleString("OK")).unwrap(), "+OK\r\n");
+        assert_eq!(to_string(&SimpleString("PONG".to_owned())).unwrap(), "+PONG\r\n");
+        assert_eq!(to_string(&SimpleString("")).unwrap(), "+\r\n");
+        assert_eq!(to_string(&vec![SimpleString("OK"), SimpleString("QUEUED")]).unwrap(), "*2\r\n+OK\r\n+QUEUED\r\n");
+        assert_eq!(to_string(&Some(SimpleString("OK"))).unwrap(), "+OK\r\n");
+
+        assert!(to_string(&SimpleString("foo\r\nbar")).is_err());
+        assert!(to_string(&SimpleString("foo\n")).is_err());
+        assert!(to_string(&SimpleString("\rfoo")).is_err());
+    }
+
+    #[test]
+    fn test_serialize_map() {
+        let mut m = BTreeMap::new();
+        assert_eq!(to_string(&m).unwrap(), "*0\r\n");
+        m.insert("foo", "bar");
+        m.insert("baz", "qux");
+        assert_eq!(to_string(&m).unwrap(), "*4\r\n$3\r\nbaz\r\n$3\r\nqux\r\n$3\r\nfoo\r\n$3\r\nbar\r\n");
+        assert_eq!(to_string(&("HSET", "mykey", &m)).unwrap(),
+                   "*3\r\n$4\r\nHSET\r\n$5\r\nmykey\r\n*4\r\n$3\r\nbaz\r\n$3\r\nqux\r\n$3\r\nfoo\r\n$3\r\nbar\r\n");
+
+        let mut m = HashMap::new();
+        m.insert("foo".to_owned(), vec![1, 2]);
+        assert_eq!(to_string(&m).unwrap(), "*2\r\n$3\r\nfoo\r\n*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+
+        // keys
+        let mut m = BTreeMap::new();
+        m.insert(10, 'a');
+        m.insert(-1, 'b');
+        assert_eq!(to_string(&m).unwrap(), "*4\r\n$2\r\n-1\r\n$1\r\nb\r\n$2\r\n10\r\n$1\r\na\r\n");
+        let options = SerializerOptions::new().integer_encoding(IntegerEncoding::Native);
+        assert_eq!(to_string_with(options, &m).unwrap(), "*4\r\n$2\r\n-1\r\n$1\r\nb\r\n$2\r\n10\r\n$1\r\na\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert(true, 1);
+        m.insert(false, 0);
+        assert_eq!(to_string(&m).unwrap(), "*4\r\n$5\r\nfalse\r\n$1\r\n0\r\n$4\r\ntrue\r\n$1\r\n1\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert('a', 1);
+        assert_eq!(to_string(&m).unwrap(), "*2\r\n$1\r\na\r\n$1\r\n1\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert(vec![1], 1);
+        assert!(to_string(&m).is_err());
+        let mut m = HashMap::new();
+        m.insert(None as Option<&str>, 1);
+        assert!(to_string(&m).is_err());
+    }
+
+    #[test]
+    fn test_serialize_struct() {
+        #[derive(Serialize)]
+        struct User<'a> {
+            name: &'a str,
+            age: u32,
+        }
+        assert_eq!(to_string(&User { name: "foo", age: 42 }).unwrap(),
+                   "*4\r\n$4\r\nname\r\n$3\r\nfoo\r\n$3\r\nage\r\n$2\r\n42\r\n");
+        assert_eq!(to_string(&("HSET", "user:1", User { name: "foo", age: 42 })).unwrap(),
+                   "*3\r\n$4\r\nHSET\r\n$6\r\nuser:1\r\n*4\r\n$4\r\nname\r\n$3\r\nfoo\r\n$3\r\nage\r\n$2\r\n42\r\n");
+
+        #[derive(Serialize)]
+        struct Empty {}
+        assert_eq!(to_string(&Empty {}).unwrap(), "*0\r\n");
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Renamed {
+            first_name: &'static str,
+            #[serde(rename = "y")]
+            year_of_birth: u16,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            email: Option<&'static str>,
+            #[serde(skip)]
+            _internal: u8,
+        }
+        assert_eq!(to_string(&Renamed { first_name: "foo", year_of_birth: 1990, email: None, _internal: 0 }).unwrap(),
+                   "*4\r\n$9\r\nfirstName\r\n$3\r\nfoo\r\n$1\r\ny\r\n$4\r\n1990\r\n");
+        assert_eq!(to_string(&Renamed { first_name: "foo", year_of_birth: 1990, email: Some("a@b"), _internal: 0 }).unwrap(),
+                   "*6\r\n$9\r\nfirstName\r\n$3\r\nfoo\r\n$1\r\ny\r\n$4\r\n1990\r\n$5\r\nemail\r\n$3\r\na@b\r\n");
+
+        // the header must match the fields actually written
+        struct Lying(usize);
+        impl Serialize for Lying {
+            fn serialize<S: ser::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+                use serde::ser::SerializeStruct;
+                let mut state = serializer.serialize_struct("Lying", self.0)?;
+                state.serialize_field("a", &1)?;
+                state.end()
+            }
+        }
+        assert!(to_string(&Lying(1)).is_ok());
+        assert!(to_string(&Lying(0)).is_err());
+        assert!(to_string(&Lying(2)).is_err());
+    }
+
+    #[test]
+    fn test_serialize_enum() {
+        #[derive(Serialize)]
+        enum E {
+            Unit,
+            Newtype(u32),
+            Tuple(u32, &'static str),
+            Struct { a: u32 },
+        }
+
+        assert_eq!(to_string(&E::Unit).unwrap(), "$4\r\nUnit\r\n");
+        assert_eq!(to_string(&E::Newtype(1)).unwrap(), "*2\r\n$7\r\nNewtype\r\n$1\r\n1\r\n");
+        assert_eq!(to_string(&E::Tuple(1, "foo")).unwrap(), "*2\r\n$5\r\nTuple\r\n*2\r\n$1\r\n1\r\n$3\r\nfoo\r\n");
+        assert_eq!(to_string(&E::Struct { a: 1 }).unwrap(), "*2\r\n$6\r\nStruct\r\n*2\r\n$1\r\na\r\n$1\r\n1\r\n");
+        assert_eq!(to_string(&vec![E::Unit, E::Newtype(1)]).unwrap(), "*2\r\n$4\r\nUnit\r\n*2\r\n$7\r\nNewtype\r\n$1\r\n1\r\n");
+
+        #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+        #[serde(rename_all = "lowercase")]
+        enum Status {
+            Active,
+        }
+        assert_eq!(to_string(&Status::Active).unwrap(), "$6\r\nactive\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert(Status::Active, 1);
+        assert_eq!(to_string(&m).unwrap(), "*2\r\n$6\r\nactive\r\n$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn test_serialize_newtype_struct() {
+        #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+        struct UserId(u64);
+        #[derive(Serialize)]
+        struct Email(String);
+        #[derive(Serialize)]
+        struct Ids(Vec<UserId>);
+
+        assert_eq!(to_string(&UserId(100)).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&Email("a@b".to_owned())).unwrap(), "$3\r\na@b\r\n");
+        assert_eq!(to_string(&Ids(vec![UserId(1), UserId(2)])).unwrap(), "*2\r\n$1\r\n1\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&Some(UserId(100))).unwrap(), "$3\r\n100\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert(UserId(1), Email("a@b".to_owned()));
+        assert_eq!(to_string(&m).unwrap(), "*2\r\n$1\r\n1\r\n$3\r\na@b\r\n");
+    }
+
+    #[test]
+    fn test_serialize_unit_struct() {
+        #[derive(Serialize)]
+        struct Unit;
+
+        assert_eq!(to_string(&Unit).unwrap(), "$-1\r\n");
+        assert_eq!(to_string(&vec![Unit]).unwrap(), "*1\r\n$-1\r\n");
+    }
+
+    // Serialises the wrapped items through collect_seq, without telling their number upfront.
+    struct Unsized<T>(Vec<T>);
+
+    impl<T: Serialize> Serialize for Unsized<T> {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().filter(|_| true))
+        }
+    }
+
+    // Serialises the wrapped entries as a map, without telling their number upfront.
+    struct UnsizedMap<K, V>(Vec<(K, V)>);
+
+    impl<K: Serialize, V: Serialize> Serialize for UnsizedMap<K, V> {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+            use serde::ser::SerializeMap;
+            let mut state = serializer.serialize_map(None)?;
+            for (k, v) in &self.0 {
+                state.serialize_entry(k, v)?;
+            }
+            state.end()
+        }
+    }
+
+    #[test]
+    fn test_serialize_unknown_length() {
+        let stream = SerializerOptions::new().unknown_length(UnknownLength::Stream);
+
+        assert_eq!(to_string(&Unsized(vec!["foo", "bar"])).unwrap(), "*2\r\n$3\r\nfoo\r\n$3\r\nbar\r\n");
+        assert_eq!(to_string(&Unsized(Vec::<u8>::new())).unwrap(), "*0\r\n");
+        assert_eq!(to_string(&Unsized(vec![Unsized(vec![1]), Unsized(vec![])])).unwrap(), "*2\r\n*1\r\n$1\r\n1\r\n*0\r\n");
+        assert_eq!(to_string(&UnsizedMap(vec![("a", 1), ("b", 2)])).unwrap(),
+                   "*4\r\n$1\r\na\r\n$1\r\n1\r\n$1\r\nb\r\n$1\r\n2\r\n");
+        assert_eq!(to_string(&UnsizedMap(Vec::<(u8, u8)>::new())).unwrap(), "*0\r\n");
+
+        assert_eq!(to_string_with(stream, &Unsized(vec!["foo", "bar"])).unwrap(), "*?\r\n$3\r\nfoo\r\n$3\r\nbar\r\n.\r\n");
+        assert_eq!(to_string_with(stream, &UnsizedMap(Vec::<(u8, u8)>::new())).unwrap(), "*?\r\n.\r\n");
+        assert_eq!(to_string_with(stream, &UnsizedMap(vec![("a", 1)])).unwrap(), "*?\r\n$1\r\na\r\n$1\r\n1\r\n.\r\n");
+        // Known lengths are not affected.
+        assert_eq!(to_string_with(stream, &vec!["foo"]).unwrap(), "*1\r\n$3\r\nfoo\r\n");
+
+        // Options apply to buffered elements too.
+        let native = SerializerOptions::new().integer_encoding(IntegerEncoding::Native);
+        assert_eq!(to_string_with(native, &Unsized(vec![1, 2])).unwrap(), "*2\r\n:1\r\n:2\r\n");
+
+        // Nothing is written when an element fails.
+        let mut output = Vec::new();
+        assert!(to_writer(&mut output, &UnsizedMap(vec![(1.5, 1)])).is_err());
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_serialize_some_error() {
+        assert_eq!(to_string(&Some(Some(1))).unwrap(), "$1\r\n1\r\n");
+
+        // Errors of the inner value are not swallowed.
+        let mut m = BTreeMap::new();
+        m.insert(vec![1], 1);
+        let mut output = Vec::new();
+        assert!(to_writer(&mut output, &Some(m)).is_err());
+        assert!(to_string(&Some(SimpleString("a\r\n"))).is_err());
+    }
+
+    #[test]
+    fn test_serialize_null_encoding() {
+        let array = SerializerOptions::new().null_encoding(NullEncoding::Array);
+        assert_eq!(to_string_with(array, &None::<u8>).unwrap(), "*-1\r\n");
+        assert_eq!(to_string_with(array, &()).unwrap(), "*-1\r\n");
+        assert_eq!(to_string_with(array, &Some(1)).unwrap(), "$1\r\n1\r\n");
+        assert_eq!(to_string_with(array, &vec![None, Some("a")]).unwrap(), "*2\r\n*-1\r\n$1\r\na\r\n");
+
+        let bulk = SerializerOptions::new().null_encoding(NullEncoding::Bulk);
+        assert_eq!(to_string_with(bulk, &None::<u8>).unwrap(), "$-1\r\n");
+    }
+
+    #[test]
+    fn test_serialize_collect_str() {
+        use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+        struct Formatted<'a>(&'a str);
+        impl<'a> Serialize for Formatted<'a> {
+            fn serialize<S: ser::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+                serializer.collect_str(self.0)
+            }
+        }
+
+        assert_eq!(to_string(&IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))).unwrap(), "$9\r\n127.0.0.1\r\n");
+        assert_eq!(to_string(&IpAddr::V6(Ipv6Addr::LOCALHOST)).unwrap(), "$3\r\n::1\r\n");
+        assert_eq!(to_string(&Formatted("")).unwrap(), "$0\r\n\r\n");
+        assert_eq!(to_string(&Formatted("héllo")).unwrap(), "$6\r\nhéllo\r\n");
+        assert_eq!(to_string(&vec![Formatted("a"), Formatted("bc")]).unwrap(), "*2\r\n$1\r\na\r\n$2\r\nbc\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert(Ipv4Addr::new(10, 0, 0, 1), 1);
+        assert_eq!(to_string(&m).unwrap(), "*2\r\n$8\r\n10.0.0.1\r\n$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn test_serialize_bool_encoding() {
+        let text = SerializerOptions::new().bool_encoding(BoolEncoding::Text);
+        assert_eq!(to_string_with(text, &true).unwrap(), "$4\r\ntrue\r\n");
+        assert_eq!(to_string_with(text, &false).unwrap(), "$5\r\nfalse\r\n");
+
+        let integer = SerializerOptions::new().bool_encoding(BoolEncoding::Integer);
+        assert_eq!(to_string_with(integer, &true).unwrap(), "$1\r\n1\r\n");
+        assert_eq!(to_string_with(integer, &false).unwrap(), "$1\r\n0\r\n");
+        let native = integer.integer_encoding(IntegerEncoding::Native);
+        assert_eq!(to_string_with(native, &true).unwrap(), ":1\r\n");
+        assert_eq!(to_string_with(native, &false).unwrap(), ":0\r\n");
+
+        let resp3 = SerializerOptions::new().bool_encoding(BoolEncoding::Resp3);
+        assert_eq!(to_string_with(resp3, &true).unwrap(), "#t\r\n");
+        assert_eq!(to_string_with(resp3, &vec![false]).unwrap(), "*1\r\n#f\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert(true, true);
+        assert_eq!(to_string_with(native, &m).unwrap(), "*2\r\n$1\r\n1\r\n:1\r\n");
+        assert_eq!(to_string_with(resp3, &m).unwrap(), "*2\r\n$4\r\ntrue\r\n#t\r\n");
+    }
+
+    #[test]
+    fn test_serialize_float_encoding() {
+        use std::f64;
+
+        let text = SerializerOptions::new().float_encoding(FloatEncoding::Text);
+        assert_eq!(to_string_with(text, &1.34).unwrap(), "$4\r\n1.34\r\n");
+        assert_eq!(to_string_with(text, &f64::INFINITY).unwrap(), "$3\r\ninf\r\n");
+        assert_eq!(to_string_with(text, &f64::NAN).unwrap(), "$3\r\nNaN\r\n");
+
+        let redis = SerializerOptions::new().float_encoding(FloatEncoding::Redis);
+        assert_eq!(to_string_with(redis, &1.34).unwrap(), "$4\r\n1.34\r\n");
+        assert_eq!(to_string_with(redis, &-1.34_f32).unwrap(), "$5\r\n-1.34\r\n");
+        assert_eq!(to_string_with(redis, &0.1_f32).unwrap(), "$3\r\n0.1\r\n");
+        assert_eq!(to_string_with(redis, &0.0).unwrap(), "$1\r\n0\r\n");
+        assert_eq!(to_string_with(redis, &100.0).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string_with(redis, &0.0001).unwrap(), "$6\r\n0.0001\r\n");
+        assert_eq!(to_string_with(redis, &0.00001).unwrap(), "$4\r\n1e-5\r\n");
+        assert_eq!(to_string_with(redis, &-1.5e300).unwrap(), "$8\r\n-1.5e300\r\n");
+        assert_eq!(to_string_with(redis, &f64::INFINITY).unwrap(), "$3\r\ninf\r\n");
+        assert_eq!(to_string_with(redis, &f64::NEG_INFINITY).unwrap(), "$4\r\n-inf\r\n");
+        assert!(to_string_with(redis, &f64::NAN).is_err());
+        let null = redis.nan_policy(NanPolicy::Null);
+        assert_eq!(to_string_with(null, &f64::NAN).unwrap(), "$-1\r\n");
+        assert_eq!(to_string_with(null.null_encoding(NullEncoding::Array), &f64::NAN).unwrap(), "*-1\r\n");
+
+        let resp3 = SerializerOptions::new().float_encoding(FloatEncoding::Resp3);
+        assert_eq!(to_string_with(resp3, &1.34).unwrap(), ",1.34\r\n");
+        assert_eq!(to_string_with(resp3, &0.00001).unwrap(), ",1e-5\r\n");
+        assert_eq!(to_string_with(resp3, &f64::NEG_INFINITY).unwrap(), ",-inf\r\n");
+        assert_eq!(to_string_with(resp3, &vec![1.5_f32]).unwrap(), "*1\r\n,1.5\r\n");
+        assert!(to_string_with(resp3, &f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_serialize_128() {
+        assert_eq!(to_string(&-100_i128).unwrap(), "$4\r\n-100\r\n");
+        assert_eq!(to_string(&100_u128).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string(&i128::MIN).unwrap(), "$40\r\n-170141183460469231731687303715884105728\r\n");
+        assert_eq!(to_string(&u128::MAX).unwrap(), "$39\r\n340282366920938463463374607431768211455\r\n");
+
+        let native = SerializerOptions::new().integer_encoding(IntegerEncoding::Native);
+        assert_eq!(to_string_with(native, &-100_i128).unwrap(), ":-100\r\n");
+        assert_eq!(to_string_with(native, &(i64::MAX as u128)).unwrap(), ":9223372036854775807\r\n");
+        assert_eq!(to_string_with(native, &(i64::MAX as u128 + 1)).unwrap(), "$19\r\n9223372036854775808\r\n");
+
+        let resp3 = SerializerOptions::new().big_number_encoding(BigNumberEncoding::Resp3);
+        assert_eq!(to_string_with(resp3, &100_u128).unwrap(), "$3\r\n100\r\n");
+        assert_eq!(to_string_with(resp3, &(i64::MIN as i128 - 1)).unwrap(), "(-9223372036854775809\r\n");
+        assert_eq!(to_string_with(resp3, &u128::MAX).unwrap(), "(340282366920938463463374607431768211455\r\n");
+        assert_eq!(to_string_with(resp3, &u64::MAX).unwrap(), "(18446744073709551615\r\n");
+        assert_eq!(to_string_with(resp3.integer_encoding(IntegerEncoding::Native), &1_i128).unwrap(), ":1\r\n");
+
+        let mut m = BTreeMap::new();
+        m.insert(u128::MAX, 1_u128);
+        assert_eq!(to_string_with(resp3, &m).unwrap(),
+                   "*2\r\n$39\r\n340282366920938463463374607431768211455\r\n$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn test_serialize_resp3() {
+        use std::collections::BTreeSet;
+        use std::f64;
+
+        let resp3 = SerializerOptions::new().protocol(Protocol::Resp3);
+
+        let mut m = BTreeMap::new();
+        m.insert("a", 1);
+        m.insert("b", 2);
+        assert_eq!(to_string_with(resp3, &m).unwrap(), "%2\r\n$1\r\na\r\n$1\r\n1\r\n$1\r\nb\r\n$1\r\n2\r\n");
+        assert_eq!(to_string_with(resp3, &BTreeMap::<u8, u8>::new()).unwrap(), "%0\r\n");
+
+        #[derive(Serialize)]
+        struct User {
+            name: &'static str,
+            admin: bool,
+        }
+        assert_eq!(to_string_with(resp3, &User { name: "foo", admin: true }).unwrap(),
+                   "%2\r\n$4\r\nname\r\n$3\r\nfoo\r\n$5\r\nadmin\r\n#t\r\n");
+
+        #[derive(Serialize)]
+        enum Point {
+            Xy { x: u8 },
+        }
+        assert_eq!(to_string_with(resp3, &Point::Xy { x: 1 }).unwrap(), "*2\r\n$2\r\nXy\r\n%1\r\n$1\r\nx\r\n$1\r\n1\r\n");
+
+        let set: BTreeSet<_> = vec!["a", "b"].into_iter().collect();
+        assert_eq!(to_string_with(resp3, &Set(&set)).unwrap(), "~2\r\n$1\r\na\r\n$1\r\nb\r\n");
+        assert_eq!(to_string_with(resp3, &Set(vec![1])).unwrap(), "~1\r\n$1\r\n1\r\n");
+        assert_eq!(to_string(&Set(&set)).unwrap(), "*2\r\n$1\r\na\r\n$1\r\nb\r\n");
+        assert!(to_string_with(resp3, &Set("a")).is_err());
+
+        assert_eq!(to_string_with(resp3, &Verbatim::text("Some string")).unwrap(), "=15\r\ntxt:Some string\r\n");
+        assert_eq!(to_string_with(resp3, &Verbatim::markdown("# a")).unwrap(), "=7\r\nmkd:# a\r\n");
+        assert_eq!(to_string(&Verbatim::text("Some string")).unwrap(), "$11\r\nSome string\r\n");
+        assert!(to_string_with(resp3, &Verbatim { format: "text", text: "a" }).is_err());
+
+        // a verbatim map key is encoded as its text
+        let mut ser = Serializer::new(Vec::new()).with_options(resp3);
+        let mut map = ser::Serializer::serialize_map(&mut ser, Some(1)).unwrap();
+        ser::SerializeMap::serialize_entry(&mut map, &Verbatim::text("a"), &1).unwrap();
+        ser::SerializeMap::end(map).unwrap();
+        assert_eq!(ser.into_inner(), b"%1\r\n$1\r\na\r\n$1\r\n1\r\n");
+
+        assert_eq!(to_string_with(resp3, &None::<u8>).unwrap(), "_\r\n");
+        assert_eq!(to_string_with(resp3, &()).unwrap(), "_\r\n");
+        assert_eq!(to_string_with(resp3, &false).unwrap(), "#f\r\n");
+        assert_eq!(to_string_with(resp3, &1.5).unwrap(), ",1.5\r\n");
+        assert_eq!(to_string_with(resp3, &f64::INFINITY).unwrap(), ",inf\r\n");
+        assert_eq!(to_string_with(resp3, &u128::MAX).unwrap(), "(340282366920938463463374607431768211455\r\n");
+        assert_eq!(to_string_with(resp3, &SimpleString("OK")).unwrap(), "+OK\r\n");
+        assert_eq!(to_string_with(resp3, &vec!["a"]).unwrap(), "*1\r\n$1\r\na\r\n");
+
+        // Unknown lengths keep the RESP3 type marker.
+        assert_eq!(to_string_with(resp3, &UnsizedMap(vec![("a", 1)])).unwrap(), "%1\r\n$1\r\na\r\n$1\r\n1\r\n");
+        assert_eq!(to_string_with(resp3.unknown_length(UnknownLength::Stream), &UnsizedMap(vec![("a", 1)])).unwrap(),
+                   "%?\r\n$1\r\na\r\n$1\r\n1\r\n.\r\n");
+        assert_eq!(to_string_with(resp3.unknown_length(UnknownLength::Stream), &Set(Unsized(vec![1]))).unwrap(),
+                   "~?\r\n$1\r\n1\r\n.\r\n");
+
+        // Encodings set explicitly win over the protocol, in whichever order they are set.
+        let custom = resp3.null_encoding(NullEncoding::Bulk).integer_encoding(IntegerEncoding::Native);
+        assert_eq!(to_string_with(custom, &(None::<u8>, 1, true)).unwrap(), "*3\r\n$-1\r\n:1\r\n#t\r\n");
+        let custom = SerializerOptions::new().bool_encoding(BoolEncoding::Integer).null_encoding(NullEncoding::Array);
+        assert_eq!(to_string_with(custom.protocol(Protocol::Resp2), &(true, None::<u8>)).unwrap(), "*2\r\n$1\r\n1\r\n*-1\r\n");
+        assert_eq!(to_string_with(custom.protocol(Protocol::Resp3), &(true, None::<u8>, 1.5)).unwrap(), "*3\r\n$1\r\n1\r\n*-1\r\n,1.5\r\n");
+        assert_eq!(to_string_with(resp3.protocol(Protocol::Resp2), &(true, None::<u8>)).unwrap(), "*2\r\n$4\r\ntrue\r\n$-1\r\n");
+    }
+
+    #[test]
+    fn test_serialize_collect_str_unstable_display() {
+        use std::cell::Cell;
+
+        // Grows by one character every time it is formatted.
+        struct Growing(Cell<usize>);
+        impl fmt::Display for Growing {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.set(self.0.get() + 1);
+                f.write_str(&"a".repeat(self.0.get()))
+            }
+        }
+        impl Serialize for Growing {
+            fn serialize<S: ser::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        let err = to_vec(&Growing(Cell::new(0))).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::SerError(_)));
+    }
 }